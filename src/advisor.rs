@@ -0,0 +1,680 @@
+use crate::dice_states::TransitionFunction;
+use crate::distribution_evaluation::{self, DISTRIBUTION_DATASET, MAX_SCORE};
+use crate::errors::Error;
+use crate::game_record::GameRecord;
+use crate::head_to_head::HeadToHeadSolver;
+use crate::metadata;
+use crate::multiplayer;
+use crate::objectives::Objective;
+use crate::reward_evaluation::{self, IntKeyedArrayMap};
+use crate::rule_sets::RuleSet;
+use crate::score_states::{ScoreAction, ScoreState, MAX_BANKED_REROLLS};
+use crate::target_evaluation;
+use crate::utility_evaluation;
+use ndarray::prelude::*;
+use std::io::Write;
+
+// A score action, the points it scores and its value for the objective
+type ScoreActionValue = (ScoreAction, u16, f32);
+
+pub fn run_session(
+    all_dice_states: &[Array1<u8>],
+    transition_function: &TransitionFunction,
+    rule_set: RuleSet,
+    objective: &Objective,
+    hdf5_file: &hdf5::File,
+) -> Result<(), Error> {
+    // Play one full game from an empty scorecard, advising on every keep and score action.
+    reward_evaluation::check_complete(hdf5_file, rule_set)?;
+    let first_roll_probability = transition_function.first_roll_probability();
+    let num_score_actions = rule_set.score_actions().len();
+
+    let mut game_record = GameRecord::new(rule_set);
+    while !game_record.is_complete() {
+        // Layer N holds the ScoreStates with N boxes left to fill, layer 0 (terminal) is not
+        // stored since its reward is only the terminal_reward.
+        let score_state = game_record.score_state();
+        let layer = num_score_actions - score_state.num_taken();
+        let layer_group = hdf5_file.group(&rule_set.layer_group_name(layer))?;
+        let next_layer_group = if layer > 1 {
+            Some(hdf5_file.group(&rule_set.layer_group_name(layer - 1))?)
+        } else {
+            None
+        };
+        // The value of every roll of this turn, and of the next turn's ScoreStates
+        let (score_state_value, next_layer_value) = match objective {
+            Objective::MaximizeMean => read_score_state_reward(
+                all_dice_states,
+                transition_function,
+                hdf5_file,
+                score_state,
+                rule_set,
+            )?,
+            Objective::BeatTarget(target) => {
+                // Only the turn start probability is stored, so this turn is recalculated for
+                // the points still needed
+                let next_layer_probability = match &next_layer_group {
+                    Some(next_layer_group) => {
+                        read_objective_turn_start(next_layer_group, objective)?
+                    }
+                    None => target_evaluation::terminal_target_probability(*target, rule_set),
+                };
+                let score_state_probability =
+                    target_evaluation::calculate_score_state_target_probability(
+                        score_state,
+                        &next_layer_probability,
+                        all_dice_states,
+                        transition_function,
+                        rule_set,
+                        *target,
+                    );
+                let need = target.saturating_sub(game_record.banked_reward()) as usize;
+                (
+                    score_state_probability.slice(s![.., .., need]).to_owned(),
+                    next_layer_probability,
+                )
+            }
+            Objective::MaximizeUtility(utility) => {
+                // Same as the target probability, this turn is recalculated for the points
+                // banked so far
+                let next_layer_utility = match &next_layer_group {
+                    Some(next_layer_group) => {
+                        read_objective_turn_start(next_layer_group, objective)?
+                    }
+                    None => utility_evaluation::terminal_utility(utility, rule_set),
+                };
+                let score_state_utility = utility_evaluation::calculate_score_state_utility(
+                    score_state,
+                    &next_layer_utility,
+                    all_dice_states,
+                    transition_function,
+                    rule_set,
+                );
+                let banked_reward = game_record.banked_reward() as usize;
+                (
+                    score_state_utility
+                        .slice(s![.., .., banked_reward])
+                        .to_owned(),
+                    next_layer_utility,
+                )
+            }
+        };
+
+        println!();
+        println!("Turn {}: {}", score_state.num_taken() + 1, score_state);
+        println!("Running total: {}", game_record.score());
+        if let Objective::BeatTarget(target) = objective {
+            println!(
+                "Probability of beating {}: {}",
+                target,
+                first_roll_probability
+                    .dot(&score_state_value.slice(s![rule_set.num_rolls - 1, ..]))
+            );
+        }
+        if layer_group.link_exists(DISTRIBUTION_DATASET) {
+            // The distribution is the one of the remaining score when maximizing the mean
+            let distribution_map = distribution_evaluation::read_distribution(&layer_group)?;
+            let distribution = distribution_map
+                .get(score_state.into())
+                .expect("distribution_map should contain all ScoreStates of the layer");
+            let distribution = distribution.row(0);
+            let banked_reward = game_record.banked_reward() as usize;
+            println!(
+                "Final score: mean {}, median {}, standard deviation {}, 10%-90% {}-{}",
+                banked_reward as f32 + distribution_evaluation::mean(distribution),
+                banked_reward + distribution_evaluation::median(distribution),
+                distribution_evaluation::variance(distribution).sqrt(),
+                banked_reward + distribution_evaluation::quantile(distribution, 0.1),
+                banked_reward + distribution_evaluation::quantile(distribution, 0.9),
+            );
+        }
+
+        let (dice_state, num_rerolls) = read_rolls(
+            all_dice_states,
+            transition_function,
+            score_state_value.view(),
+            reward_evaluation::turn_start_rerolls(score_state, rule_set),
+        )?;
+
+        // We want to choose the best score action now instead of the keep action
+        let score_action_value = score_state
+            .legal_score_actions(&dice_state, rule_set)
+            .into_iter()
+            .map(|score_action| {
+                let action_reward =
+                    score_state.reward_on_roll(score_action, &dice_state, num_rerolls, rule_set);
+                let child_score_state = rule_set.bank_rerolls(
+                    score_state
+                        .apply_action(score_action, &dice_state, rule_set)
+                        .expect("legal_score_actions should only return valid actions"),
+                    num_rerolls,
+                );
+                let all_child_values = next_layer_value.get(child_score_state.into()).expect(
+                    "next_layer_value should contain all ScoreStates reachable from ScoreState",
+                );
+                let action_value = match objective {
+                    Objective::MaximizeMean => action_reward as f32 + all_child_values[[0, 0]],
+                    Objective::BeatTarget(target) => {
+                        let need = target.saturating_sub(game_record.banked_reward());
+                        if need <= action_reward {
+                            1.0
+                        } else {
+                            all_child_values[[0, (need - action_reward) as usize]]
+                        }
+                    }
+                    Objective::MaximizeUtility(_) => {
+                        let child_banked_reward =
+                            (game_record.banked_reward() + action_reward) as usize;
+                        all_child_values[[0, child_banked_reward.min(MAX_SCORE)]]
+                    }
+                };
+                (score_action, action_reward, action_value)
+            })
+            .collect::<Vec<_>>();
+        let score_action = read_score_action(score_action_value)?;
+        game_record
+            .apply_action_on_roll(score_action, &dice_state, num_rerolls)
+            .expect("legal_score_actions should only return valid actions");
+        if rule_set.has_banked_rerolls() && num_rerolls > MAX_BANKED_REROLLS as usize {
+            println!(
+                "Only {} of the {} rerolls left can be banked, the others are lost",
+                MAX_BANKED_REROLLS, num_rerolls
+            );
+        }
+        game_record.bank_rerolls(num_rerolls);
+    }
+
+    println!();
+    println!("{}", game_record);
+    Ok(())
+}
+
+// Up to this number of boxes left to both players the win probability is solved exactly. Before
+// that, the advice maximizes the probability of beating the opponent's final score distribution
+// over the current turn, like with more players, so that the opponent's lead still matters.
+const MAX_HEAD_TO_HEAD_BOXES_LEFT: usize = 4;
+
+pub fn run_head_to_head_session(
+    all_dice_states: &[Array1<u8>],
+    transition_function: &TransitionFunction,
+    rule_set: RuleSet,
+    hdf5_file: &hdf5::File,
+) -> Result<(), Error> {
+    // Play one full game against an opponent, advising on the player's turns to maximize the
+    // probability of winning. The opponent's turns only need their final dice and score action.
+    reward_evaluation::check_complete(hdf5_file, rule_set)?;
+    let first_roll_probability = transition_function.first_roll_probability();
+    let mut head_to_head_solver =
+        HeadToHeadSolver::new(all_dice_states, transition_function, rule_set);
+
+    let mut player_record = GameRecord::new(rule_set);
+    let mut opponent_record = GameRecord::new(rule_set);
+    let mut player_turn = read_yes_no("Do you play first? [y/n]: ")?;
+    while !player_record.is_complete() || !opponent_record.is_complete() {
+        let player = player_record.score_state();
+        let opponent = opponent_record.score_state();
+        println!();
+        println!(
+            "Running totals: you {}, opponent {}",
+            player_record.score(),
+            opponent_record.score()
+        );
+        if !player_turn {
+            println!("Opponent turn {}: {}", opponent.num_taken() + 1, opponent);
+            read_opponent_turn(all_dice_states, rule_set, &mut opponent_record)?;
+            player_turn = player_record.is_complete();
+            continue;
+        }
+
+        println!("Your turn {}: {}", player.num_taken() + 1, player);
+        let difference =
+            player_record.banked_reward() as i32 - opponent_record.banked_reward() as i32;
+        let boxes_left =
+            2 * rule_set.score_actions().len() - player.num_taken() - opponent.num_taken();
+        let legal_score_actions = |dice_state: &Array1<u8>| {
+            player
+                .legal_score_actions(dice_state, rule_set)
+                .into_iter()
+                .map(|score_action| {
+                    (
+                        score_action,
+                        player.reward(score_action, dice_state, rule_set),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let (dice_state, score_action_value) = if boxes_left <= MAX_HEAD_TO_HEAD_BOXES_LEFT {
+            let turn_win_probability =
+                head_to_head_solver.turn_win_probability(player, opponent, difference);
+            println!(
+                "Win probability: {}",
+                first_roll_probability
+                    .dot(&turn_win_probability.slice(s![rule_set.num_rolls - 1, ..]))
+            );
+            let (dice_state, _) = read_rolls(
+                all_dice_states,
+                transition_function,
+                turn_win_probability.view(),
+                rule_set.num_rolls - 1,
+            )?;
+            let score_action_value = legal_score_actions(&dice_state)
+                .into_iter()
+                .map(|(score_action, action_reward)| {
+                    let win_probability = head_to_head_solver.score_action_win_probability(
+                        player,
+                        opponent,
+                        difference,
+                        score_action,
+                        &dice_state,
+                    );
+                    (score_action, action_reward, win_probability)
+                })
+                .collect::<Vec<_>>();
+            (dice_state, score_action_value)
+        } else {
+            println!(
+                "Too many boxes left to solve the win probability exactly, assuming both players \
+                 maximize their expected score after this turn"
+            );
+            let opponent_distribution =
+                opponent_final_score_distribution(&opponent_record, hdf5_file)?;
+            read_first_place_turn(
+                all_dice_states,
+                transition_function,
+                &player_record,
+                &[opponent_distribution],
+                "Win probability",
+                hdf5_file,
+            )?
+        };
+        let score_action = read_score_action(score_action_value)?;
+        player_record
+            .apply_action(score_action, &dice_state)
+            .expect("legal_score_actions should only return valid actions");
+        player_turn = opponent_record.is_complete();
+    }
+
+    println!();
+    println!("You:\n{}", player_record);
+    println!();
+    println!("Opponent:\n{}", opponent_record);
+    Ok(())
+}
+
+pub fn run_multiplayer_session(
+    all_dice_states: &[Array1<u8>],
+    transition_function: &TransitionFunction,
+    rule_set: RuleSet,
+    num_players: usize,
+    hdf5_file: &hdf5::File,
+) -> Result<(), Error> {
+    // Play one full game with every player in turn, advising on the player's turns to maximize
+    // the probability of finishing first. The opponents' turns only need their final dice and
+    // score action.
+    reward_evaluation::check_complete(hdf5_file, rule_set)?;
+    let seat = loop {
+        match read_line(&format!("Your seat (0-{}): ", num_players - 1))?.parse::<usize>() {
+            Ok(seat) if seat < num_players => break seat,
+            _ => println!("Invalid seat, expected 0-{}", num_players - 1),
+        }
+    };
+
+    let mut game_records = vec![GameRecord::new(rule_set); num_players];
+    let mut current_player = 0;
+    while game_records
+        .iter()
+        .any(|game_record| !game_record.is_complete())
+    {
+        println!();
+        for (player, game_record) in game_records.iter().enumerate() {
+            println!(
+                "Running total of player {}: {}",
+                player,
+                game_record.score()
+            );
+        }
+        if current_player != seat {
+            let game_record = &mut game_records[current_player];
+            println!(
+                "Player {} turn {}: {}",
+                current_player,
+                game_record.score_state().num_taken() + 1,
+                game_record.score_state()
+            );
+            read_opponent_turn(all_dice_states, rule_set, game_record)?;
+            current_player = (current_player + 1) % num_players;
+            continue;
+        }
+
+        let opponent_distributions = game_records
+            .iter()
+            .enumerate()
+            .filter(|(player, _)| *player != seat)
+            .map(|(_, game_record)| opponent_final_score_distribution(game_record, hdf5_file))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let game_record = &mut game_records[seat];
+        let score_state = game_record.score_state();
+        println!("Your turn {}: {}", score_state.num_taken() + 1, score_state);
+        let (dice_state, score_action_value) = read_first_place_turn(
+            all_dice_states,
+            transition_function,
+            game_record,
+            &opponent_distributions,
+            "Probability of finishing first",
+            hdf5_file,
+        )?;
+        let score_action = read_score_action(score_action_value)?;
+        game_record
+            .apply_action(score_action, &dice_state)
+            .expect("legal_score_actions should only return valid actions");
+        current_player = (current_player + 1) % num_players;
+    }
+
+    for (player, game_record) in game_records.iter().enumerate() {
+        println!();
+        println!("Player {}:\n{}", player, game_record);
+    }
+    Ok(())
+}
+
+fn opponent_final_score_distribution(
+    game_record: &GameRecord,
+    hdf5_file: &hdf5::File,
+) -> Result<Array1<f32>, Error> {
+    // The final score distribution of an opponent who keeps maximizing their expected score
+    let rule_set = game_record.rule_set();
+    let score_state = game_record.score_state();
+    let layer_distribution = multiplayer::read_layer_distribution(
+        hdf5_file,
+        rule_set.score_actions().len() - score_state.num_taken(),
+        rule_set,
+    )?;
+    Ok(multiplayer::final_score_distribution(
+        layer_distribution.as_ref(),
+        score_state,
+        game_record.banked_reward(),
+        rule_set,
+    ))
+}
+
+fn read_first_place_turn(
+    all_dice_states: &[Array1<u8>],
+    transition_function: &TransitionFunction,
+    game_record: &GameRecord,
+    opponent_distributions: &[Array1<f32>],
+    probability_name: &str,
+    hdf5_file: &hdf5::File,
+) -> Result<(Array1<u8>, Vec<ScoreActionValue>), Error> {
+    // Read the rolls of a turn maximizing the probability of finishing above every opponent, and
+    // return the final dice along with the value of every score action
+    let rule_set = game_record.rule_set();
+    let score_state = game_record.score_state();
+    let banked_reward = game_record.banked_reward();
+    let num_boxes_left = rule_set.score_actions().len() - score_state.num_taken();
+    let first_place_probability = multiplayer::first_place_probability(opponent_distributions);
+    let layer_distribution =
+        multiplayer::read_layer_distribution(hdf5_file, num_boxes_left, rule_set)?;
+    println!(
+        "{}: {}",
+        probability_name,
+        multiplayer::final_score_distribution(
+            layer_distribution.as_ref(),
+            score_state,
+            banked_reward,
+            rule_set,
+        )
+        .dot(&first_place_probability)
+    );
+    let next_layer_distribution =
+        multiplayer::read_layer_distribution(hdf5_file, num_boxes_left - 1, rule_set)?;
+    let score_state_probability = multiplayer::calculate_score_state_first_place_probability(
+        score_state,
+        banked_reward,
+        next_layer_distribution.as_ref(),
+        first_place_probability.view(),
+        all_dice_states,
+        transition_function,
+        rule_set,
+    );
+    let (dice_state, _) = read_rolls(
+        all_dice_states,
+        transition_function,
+        score_state_probability.view(),
+        rule_set.num_rolls - 1,
+    )?;
+    let score_action_value = score_state
+        .legal_score_actions(&dice_state, rule_set)
+        .into_iter()
+        .map(|score_action| {
+            let probability = multiplayer::score_action_first_place_probability(
+                score_state,
+                banked_reward,
+                next_layer_distribution.as_ref(),
+                first_place_probability.view(),
+                score_action,
+                &dice_state,
+                rule_set,
+            );
+            (
+                score_action,
+                score_state.reward(score_action, &dice_state, rule_set),
+                probability,
+            )
+        })
+        .collect::<Vec<_>>();
+    Ok((dice_state, score_action_value))
+}
+
+fn read_opponent_turn(
+    all_dice_states: &[Array1<u8>],
+    rule_set: RuleSet,
+    game_record: &mut GameRecord,
+) -> Result<(), Error> {
+    // Only the final dice and the chosen score action of an opponent matter
+    let score_state = game_record.score_state();
+    let dice_state = read_dice_state(all_dice_states, "Opponent dice state: ")?;
+    let legal_score_actions = score_state.legal_score_actions(&dice_state, rule_set);
+    for (option, score_action) in legal_score_actions.iter().enumerate() {
+        println!(
+            "{}: Score action: {:?} (+{})",
+            option,
+            score_action,
+            score_state.reward(*score_action, &dice_state, rule_set)
+        );
+    }
+    let score_action = legal_score_actions[read_option(legal_score_actions.len())?];
+    game_record
+        .apply_action(score_action, &dice_state)
+        .expect("legal_score_actions should only return valid actions");
+    Ok(())
+}
+
+fn read_objective_turn_start(
+    layer_group: &hdf5::Group,
+    objective: &Objective,
+) -> Result<IntKeyedArrayMap, Error> {
+    // The turn start value of every ScoreState of a layer for a derived objective
+    let dataset_name = objective.dataset_name();
+    metadata::check_objective_metadata(&layer_group.dataset(&dataset_name)?, objective)?;
+    IntKeyedArrayMap::read_turn_start_from_group(layer_group, &dataset_name)
+}
+
+fn read_score_state_reward(
+    all_dice_states: &[Array1<u8>],
+    transition_function: &TransitionFunction,
+    hdf5_file: &hdf5::File,
+    score_state: ScoreState,
+    rule_set: RuleSet,
+) -> Result<(Array2<f32>, IntKeyedArrayMap), Error> {
+    // The expected reward of every roll of the turn, and of the next turn's ScoreStates. Only
+    // the turn start reward is needed from the file, this turn is recalculated from the next
+    // layer's.
+    let layer = rule_set.score_actions().len() - score_state.num_taken();
+    let next_layer_reward = if layer > 1 {
+        reward_evaluation::read_turn_start_reward(
+            &hdf5_file.group(&rule_set.layer_group_name(layer - 1))?,
+        )?
+    } else {
+        reward_evaluation::terminal_score_state_reward(rule_set)
+    };
+    let score_state_reward = reward_evaluation::calculate_score_state_reward(
+        score_state,
+        &next_layer_reward,
+        all_dice_states,
+        transition_function,
+        rule_set,
+    );
+    Ok((score_state_reward, next_layer_reward))
+}
+
+fn read_rolls(
+    all_dice_states: &[Array1<u8>],
+    transition_function: &TransitionFunction,
+    score_state_value: ArrayView2<f32>,
+    num_rerolls: usize,
+) -> Result<(Array1<u8>, usize), Error> {
+    // Read the rolls of a turn, advising on every keep action, and return the final dice along
+    // with the rerolls left
+    let mut dice_state = read_dice_state(all_dice_states, "Dice state: ")?;
+    let mut reroll = num_rerolls;
+    while reroll > 0 {
+        let dice_state_index = all_dice_states
+            .iter()
+            .position(|x| x == dice_state)
+            .expect("read_dice_state should only return valid dice states");
+        println!("Rerolls left: {}", reroll);
+        let next_roll_state_value = score_state_value.slice(s![reroll - 1, ..]);
+        let kept_state_value = transition_function.probability.dot(&next_roll_state_value);
+        let mut keep_action_value = transition_function.keep_actions[dice_state_index]
+            .iter()
+            .map(|&kept_state_index| (kept_state_index, kept_state_value[kept_state_index]))
+            .collect::<Vec<_>>();
+        keep_action_value.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap());
+        // Best keep action is printed last, right above the prompt
+        for (kept_state_index, keep_action_value) in keep_action_value.iter().rev() {
+            println!(
+                "Value: {}: Kept dice: {}",
+                keep_action_value, transition_function.all_kept_states[*kept_state_index]
+            );
+        }
+        // With banked rerolls or served bonuses, the value of the roll is above every keep
+        // action when scoring now is better
+        let score_now_value = score_state_value[[reroll, dice_state_index]];
+        if keep_action_value
+            .first()
+            .is_some_and(|&(_, best_keep_action_value)| score_now_value > best_keep_action_value)
+        {
+            println!(
+                "Value: {}: Keep every dice to score now with {} rerolls left",
+                score_now_value, reroll
+            );
+        }
+
+        let kept_dice = read_kept_dice(&dice_state)?;
+        if kept_dice == dice_state {
+            // Keeping every dice ends the rolling phase early
+            break;
+        }
+        dice_state = loop {
+            let next_dice_state = read_dice_state(all_dice_states, "Dice state: ")?;
+            if kept_dice
+                .iter()
+                .zip(next_dice_state.iter())
+                .all(|(&k, &n)| k <= n)
+            {
+                break next_dice_state;
+            }
+            println!("Dice state must contain the kept dice");
+        };
+        reroll -= 1;
+    }
+    Ok((dice_state, reroll))
+}
+
+fn read_score_action(mut score_action_value: Vec<ScoreActionValue>) -> Result<ScoreAction, Error> {
+    // Print the score actions from worst to best and read the chosen one
+    score_action_value.sort_by(|(_, _, a), (_, _, b)| b.partial_cmp(a).unwrap());
+    for (option, (score_action, action_reward, action_value)) in
+        score_action_value.iter().enumerate().rev()
+    {
+        println!(
+            "{}: Value: {}: Score action: {:?} (+{})",
+            option, action_value, score_action, action_reward
+        );
+    }
+    let (score_action, _, _) = score_action_value[read_option(score_action_value.len())?];
+    Ok(score_action)
+}
+
+fn read_line(prompt: &str) -> Result<String, Error> {
+    print!("{}", prompt);
+    std::io::stdout().flush()?;
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line)?;
+    Ok(line.trim().to_string())
+}
+
+fn parse_dice_counts(line: &str) -> Option<Array1<u8>> {
+    // Dice are given in the format: num_1s,num_2s,num_3s,num_4s,num_5s,num_6s
+    let dice_counts = line
+        .split(',')
+        .map(|x| x.trim().parse::<u8>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    if dice_counts.len() != 6 {
+        return None;
+    }
+    Some(Array1::from_vec(dice_counts))
+}
+
+fn read_dice_state(all_dice_states: &[Array1<u8>], prompt: &str) -> Result<Array1<u8>, Error> {
+    loop {
+        match parse_dice_counts(&read_line(prompt)?) {
+            Some(dice_state) if all_dice_states.contains(&dice_state) => return Ok(dice_state),
+            _ => println!(
+                "Invalid dice state, expected {} dices",
+                all_dice_states[0].sum()
+            ),
+        }
+    }
+}
+
+fn read_kept_dice(dice_state: &Array1<u8>) -> Result<Array1<u8>, Error> {
+    loop {
+        match parse_dice_counts(&read_line("Kept dice: ")?) {
+            Some(kept_dice)
+                if kept_dice
+                    .iter()
+                    .zip(dice_state.iter())
+                    .all(|(&k, &d)| k <= d) =>
+            {
+                return Ok(kept_dice)
+            }
+            _ => println!("Invalid kept dice, must be a subset of {}", dice_state),
+        }
+    }
+}
+
+fn read_yes_no(prompt: &str) -> Result<bool, Error> {
+    loop {
+        match read_line(prompt)?.to_lowercase().as_str() {
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => println!("Invalid answer, expected y or n"),
+        }
+    }
+}
+
+fn read_option(num_options: usize) -> Result<usize, Error> {
+    // An empty answer picks the best option
+    loop {
+        let line = read_line("Score action (empty for best): ")?;
+        if line.is_empty() {
+            return Ok(0);
+        }
+        match line.parse::<usize>() {
+            Ok(option) if option < num_options => return Ok(option),
+            _ => println!("Invalid option, expected 0-{}", num_options - 1),
+        }
+    }
+}
@@ -3,98 +3,262 @@ use ndarray::prelude::*;
 
 pub const NUM_DICES: usize = 5;
 
-pub fn get_transition_function(
-    all_dice_states: &[Array1<u8>],
-    all_keep_actions: &[Array1<bool>],
-) -> Array3<f32> {
-    // Generate the transition function when rolling NUM_DICES dice and keeping a subset of them.
+pub struct TransitionFunction {
+    // Every multiset of 0 to num_dice kept dice, by number of dice and then in the order of
+    // get_all_dice_states, so that the first one keeps nothing
+    pub all_kept_states: Vec<Array1<u8>>,
+    // The probability of rolling every dice state after keeping every kept state, of shape
+    // (num_kept_states, num_dice_states)
+    pub probability: TransitionProbability,
+    // The keep actions of every dice state, as the indexes of its distinct kept states. Keeping
+    // different dice of the same face leads to the same kept state, so there are at most
+    // 2^num_dice: 32 with five dice and 64 with six.
+    pub keep_actions: Vec<Vec<usize>>,
+    // Keeping nothing can roll any dice state, so the first row is stored densely
+    first_roll_probability: Array1<f32>,
+}
 
-    let num_dice_states = all_dice_states.len();
-    let num_keep_actions = all_keep_actions.len();
+impl TransitionFunction {
+    pub fn new(all_dice_states: &[Array1<u8>]) -> Self {
+        // Generate the transition function when rolling the dice and keeping a subset of them.
+        Self::with_probability(all_dice_states, get_transition_probability(all_dice_states))
+    }
+    pub fn with_probability(all_dice_states: &[Array1<u8>], probability: Array2<f32>) -> Self {
+        // The kept states and keep actions only depend on the dice, so only the probability is
+        // saved
+        let num_dice = all_dice_states[0].sum() as usize;
+        let all_kept_states = get_all_kept_states(num_dice);
+        let keep_actions = all_dice_states
+            .iter()
+            .map(|dice_state| {
+                all_kept_states
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, kept_state)| {
+                        kept_state
+                            .iter()
+                            .zip(dice_state.iter())
+                            .all(|(&k, &d)| k <= d)
+                    })
+                    .map(|(kept_state_index, _)| kept_state_index)
+                    .collect()
+            })
+            .collect();
+        Self {
+            all_kept_states,
+            first_roll_probability: probability.row(0).to_owned(),
+            probability: TransitionProbability::Sparse(SparseProbability::from_dense(
+                probability.view(),
+            )),
+            keep_actions,
+        }
+    }
+    pub fn to_dense(&self) -> Self {
+        // The same transition function with dense dot products, to compare both
+        Self {
+            all_kept_states: self.all_kept_states.clone(),
+            probability: TransitionProbability::Dense(self.probability.to_dense()),
+            keep_actions: self.keep_actions.clone(),
+            first_roll_probability: self.first_roll_probability.clone(),
+        }
+    }
+    pub fn first_roll_probability(&self) -> ArrayView1<'_, f32> {
+        // The probablity of rolling any state by rerolling all dices
+        self.first_roll_probability.view()
+    }
+}
 
-    let mut transition_function: Array3<f32> =
-        Array3::zeros((num_dice_states, num_keep_actions, num_dice_states));
+pub enum TransitionProbability {
+    Dense(Array2<f32>),
+    Sparse(SparseProbability),
+}
 
-    transition_function
+impl TransitionProbability {
+    pub fn dot(&self, values: &ArrayView1<f32>) -> Array1<f32> {
+        // The expected value of every kept state, from the value of every dice state
+        match self {
+            Self::Dense(probability) => probability.dot(values),
+            Self::Sparse(probability) => probability.dot(values),
+        }
+    }
+    pub fn dot_columns(&self, values: &ArrayView2<f32>) -> Array2<f32> {
+        // Same as dot for every column of values, of shape (num_dice_states, num_columns)
+        match self {
+            Self::Dense(probability) => probability.dot(values),
+            Self::Sparse(probability) => probability.dot_columns(values),
+        }
+    }
+    pub fn row_dot_columns(
+        &self,
+        kept_state_index: usize,
+        values: &ArrayView2<f32>,
+    ) -> Array1<f32> {
+        // Same as dot_columns for a single kept state
+        match self {
+            Self::Dense(probability) => probability.row(kept_state_index).dot(values),
+            Self::Sparse(probability) => probability.row_dot_columns(kept_state_index, values),
+        }
+    }
+    pub fn to_dense(&self) -> Array2<f32> {
+        match self {
+            Self::Dense(probability) => probability.clone(),
+            Self::Sparse(probability) => probability.to_dense(),
+        }
+    }
+    pub fn num_nonzeros(&self) -> usize {
+        match self {
+            Self::Dense(probability) => probability.iter().filter(|&&x| x != 0.0).count(),
+            Self::Sparse(probability) => probability.values.len(),
+        }
+    }
+}
+
+pub struct SparseProbability {
+    // Compressed sparse rows: the nonzero probabilities of kept state i are
+    // values[row_offsets[i]..row_offsets[i + 1]], rolling the dice states of the same range of
+    // dice_state_indexes
+    row_offsets: Vec<usize>,
+    dice_state_indexes: Vec<usize>,
+    values: Vec<f32>,
+    num_dice_states: usize,
+}
+
+impl SparseProbability {
+    pub fn from_dense(probability: ArrayView2<f32>) -> Self {
+        let mut row_offsets = vec![0];
+        let mut dice_state_indexes = Vec::new();
+        let mut values = Vec::new();
+        for row in probability.outer_iter() {
+            for (dice_state_index, &value) in row.iter().enumerate() {
+                if value != 0.0 {
+                    dice_state_indexes.push(dice_state_index);
+                    values.push(value);
+                }
+            }
+            row_offsets.push(values.len());
+        }
+        Self {
+            row_offsets,
+            dice_state_indexes,
+            values,
+            num_dice_states: probability.len_of(Axis(1)),
+        }
+    }
+    fn row_range(&self, kept_state_index: usize) -> std::ops::Range<usize> {
+        self.row_offsets[kept_state_index]..self.row_offsets[kept_state_index + 1]
+    }
+    pub fn dot(&self, values: &ArrayView1<f32>) -> Array1<f32> {
+        Array1::from_shape_fn(self.row_offsets.len() - 1, |kept_state_index| {
+            self.row_range(kept_state_index)
+                .map(|k| self.values[k] * values[self.dice_state_indexes[k]])
+                .sum()
+        })
+    }
+    pub fn dot_columns(&self, values: &ArrayView2<f32>) -> Array2<f32> {
+        let mut result = Array2::zeros((self.row_offsets.len() - 1, values.len_of(Axis(1))));
+        for (kept_state_index, mut row) in result.outer_iter_mut().enumerate() {
+            for k in self.row_range(kept_state_index) {
+                row.scaled_add(self.values[k], &values.row(self.dice_state_indexes[k]));
+            }
+        }
+        result
+    }
+    pub fn row_dot_columns(
+        &self,
+        kept_state_index: usize,
+        values: &ArrayView2<f32>,
+    ) -> Array1<f32> {
+        let mut result = Array1::zeros(values.len_of(Axis(1)));
+        for k in self.row_range(kept_state_index) {
+            result.scaled_add(self.values[k], &values.row(self.dice_state_indexes[k]));
+        }
+        result
+    }
+    pub fn to_dense(&self) -> Array2<f32> {
+        let mut probability = Array2::zeros((self.row_offsets.len() - 1, self.num_dice_states));
+        for kept_state_index in 0..self.row_offsets.len() - 1 {
+            for k in self.row_range(kept_state_index) {
+                probability[[kept_state_index, self.dice_state_indexes[k]]] = self.values[k];
+            }
+        }
+        probability
+    }
+}
+
+pub fn get_transition_probability(all_dice_states: &[Array1<u8>]) -> Array2<f32> {
+    // The dense probability of rolling every dice state after keeping every kept state, which is
+    // what the file stores
+    let num_dice = all_dice_states[0].sum() as usize;
+    let all_kept_states = get_all_kept_states(num_dice);
+    let mut probability: Array2<f32> =
+        Array2::zeros((all_kept_states.len(), all_dice_states.len()));
+    probability
         .outer_iter_mut()
         .into_par_iter()
         .enumerate()
-        .for_each(|(index, mut view)| {
-            for (keep_action_index, keep_action) in all_keep_actions.iter().enumerate() {
-                for (next_dice_state_index, next_dice_state) in all_dice_states.iter().enumerate() {
-                    view[[keep_action_index, next_dice_state_index]] = transition_probability(
-                        &all_dice_states[index],
-                        keep_action,
-                        next_dice_state,
-                    );
-                }
+        .for_each(|(kept_state_index, mut view)| {
+            for (next_dice_state_index, next_dice_state) in all_dice_states.iter().enumerate() {
+                view[next_dice_state_index] =
+                    transition_probability(&all_kept_states[kept_state_index], next_dice_state);
             }
         });
+    probability
+}
 
-    transition_function
+pub fn transition_function_name(num_dice: usize) -> String {
+    // The dataset of TransitionFunction::probability. Older files hold a transition_function
+    // dataset indexed by the dice kept, which is regenerated under this name.
+    if num_dice == NUM_DICES {
+        "kept_transition_function".to_string()
+    } else {
+        format!("kept_transition_function_{}_dice", num_dice)
+    }
 }
 
-pub fn get_all_dice_states() -> Vec<Array1<u8>> {
+pub fn get_all_dice_states(num_dice: usize) -> Vec<Array1<u8>> {
+    // Every way to spread num_dice dice over the six faces, in lexicographic order of the
+    // counts of ones, twos, ...
     let mut dice_states = Vec::new();
-    for ones in 0..=NUM_DICES {
-        for twos in 0..=NUM_DICES - ones {
-            for threes in 0..=NUM_DICES - ones - twos {
-                for fours in 0..=NUM_DICES - ones - twos - threes {
-                    for fives in 0..=NUM_DICES - ones - twos - threes - fours {
-                        let sixes = NUM_DICES - ones - twos - threes - fours - fives;
-                        dice_states.push(array![
-                            ones as u8,
-                            twos as u8,
-                            threes as u8,
-                            fours as u8,
-                            fives as u8,
-                            sixes as u8
-                        ]);
-                    }
-                }
-            }
-        }
-    }
+    let mut dice_state = array![0, 0, 0, 0, 0, 0];
+    push_dice_states(&mut dice_states, &mut dice_state, 0, num_dice as u8);
     dice_states
 }
 
-pub fn get_all_keep_actions() -> Vec<Array1<bool>> {
-    let mut keep_actions = Vec::new();
-    for first in 0..2 {
-        for second in 0..2 {
-            for third in 0..2 {
-                for fourth in 0..2 {
-                    for fifth in 0..2 {
-                        keep_actions.push(array![
-                            first == 1,
-                            second == 1,
-                            third == 1,
-                            fourth == 1,
-                            fifth == 1,
-                        ]);
-                    }
-                }
-            }
-        }
+fn push_dice_states(
+    dice_states: &mut Vec<Array1<u8>>,
+    dice_state: &mut Array1<u8>,
+    face_index: usize,
+    num_dice_left: u8,
+) {
+    if face_index == dice_state.len() - 1 {
+        // The sixes take every dice left
+        dice_state[face_index] = num_dice_left;
+        dice_states.push(dice_state.clone());
+        return;
+    }
+    for num_dice in 0..=num_dice_left {
+        dice_state[face_index] = num_dice;
+        push_dice_states(
+            dice_states,
+            dice_state,
+            face_index + 1,
+            num_dice_left - num_dice,
+        );
     }
-    keep_actions
 }
 
-fn transition_probability(
-    dice_state: &Array1<u8>,
-    keep_action: &Array1<bool>,
-    next_dice_state: &Array1<u8>,
-) -> f32 {
-    // Given a state, action, and next state, return the probability of transitioning from the
-    // given state to the given next state given the given action.
+pub fn get_all_kept_states(num_dice: usize) -> Vec<Array1<u8>> {
+    // Every way to keep 0 to num_dice dice, the fewest dice first
+    (0..=num_dice).flat_map(get_all_dice_states).collect()
+}
 
-    // Get kept dices.
-    let kept = action_to_kept_array(dice_state, keep_action);
+fn transition_probability(kept_state: &Array1<u8>, next_dice_state: &Array1<u8>) -> f32 {
+    // Given the kept dice and a next state, return the probability of rolling the next state by
+    // rerolling the other dice.
 
     // If any kept dice is greater than the corresponding next dice, then the transition
-    // is impossible (you can't go from (2, 3, 1, 0, 0, 0) to (1, 3, 2, 0, 0, 0) by keeping
-    // 2 of the first dice
-    if kept
+    // is impossible (you can't go to (1, 3, 2, 0, 0, 0) by keeping (2, 0, 0, 0, 0, 0))
+    if kept_state
         .iter()
         .zip(next_dice_state.iter())
         .any(|(&x, &y)| x > y)
@@ -103,7 +267,7 @@ fn transition_probability(
     }
 
     // Get goal reroll dices.
-    let goal_reroll = next_dice_state - kept;
+    let goal_reroll = next_dice_state - kept_state;
 
     // If there are no goal reroll dices, then the transition is certain.
     if goal_reroll.iter().all(|&x| x == 0) {
@@ -114,32 +278,6 @@ fn transition_probability(
     probability_of_goal_roll(&goal_reroll)
 }
 
-fn action_to_kept_array(dice_state: &Array1<u8>, keep_action: &Array1<bool>) -> Array1<u8> {
-    // Given a state and an action, return the state that results from keeping the dice
-    // specified by the action.
-
-    // For example, if state = (2, 3, 1, 0, 0, 0) and action = (1, 0, 1, 0, 0, 0), then
-    // action_to_kept_tuple(state, action) = (1, 1, 0, 0, 0, 0).
-    let mut action_index = 0;
-    let mut kept = array![0, 0, 0, 0, 0, 0];
-
-    for (state_index, num_dice) in dice_state.iter().enumerate() {
-        for _ in 0..*num_dice {
-            if keep_action[action_index] {
-                kept[state_index] += 1;
-            }
-            action_index += 1;
-            if action_index >= keep_action.len() {
-                break;
-            }
-        }
-        if action_index >= keep_action.len() {
-            break;
-        }
-    }
-    kept
-}
-
 fn probability_of_goal_roll(goal_roll: &Array1<u8>) -> f32 {
     // Possibility of rolling sum(goal_roll) dice and
     // getting the desired positive goal_roll values.
@@ -182,3 +320,38 @@ fn binomial_coefficient(n: u64, k: u64) -> u64 {
     }
     c
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close<D: Dimension>(a: &Array<f32, D>, b: &Array<f32, D>) {
+        assert!((a - b).iter().all(|difference| difference.abs() < 1e-4));
+    }
+
+    #[test]
+    fn test_sparse_transition_probability() {
+        // The sparse dot products match the dense ones
+        let all_dice_states = get_all_dice_states(NUM_DICES);
+        let dense_probability = get_transition_probability(&all_dice_states);
+        let sparse_probability = SparseProbability::from_dense(dense_probability.view());
+        assert_eq!(sparse_probability.to_dense(), dense_probability);
+        let values = Array2::from_shape_fn((all_dice_states.len(), 3), |(i, j)| {
+            ((i * 7 + j * 13) % 31) as f32
+        });
+        let dense_value = dense_probability.dot(&values.column(0));
+        let sparse_value = sparse_probability.dot(&values.column(0));
+        assert_close(&dense_value, &sparse_value);
+        let dense_values = dense_probability.dot(&values);
+        assert_close(
+            &dense_values,
+            &sparse_probability.dot_columns(&values.view()),
+        );
+        for kept_state_index in [0, 100, dense_probability.len_of(Axis(0)) - 1] {
+            assert_close(
+                &dense_values.row(kept_state_index).to_owned(),
+                &sparse_probability.row_dot_columns(kept_state_index, &values.view()),
+            );
+        }
+    }
+}
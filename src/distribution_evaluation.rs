@@ -0,0 +1,218 @@
+use crate::dice_states::TransitionFunction;
+use crate::errors::Error;
+use crate::metadata;
+use crate::objectives::Objective;
+use crate::reward_evaluation::{self, IntKeyedArrayMap};
+use crate::rule_sets::RuleSet;
+use crate::score_states::ScoreState;
+use ndarray::prelude::*;
+use std::io::Write;
+
+// The highest possible final score: every box at its maximum, the upper bonus and 12 Yahtzee
+// bonuses. Every remaining score of every ScoreState fits in 0..=MAX_SCORE.
+pub const MAX_SCORE: usize = 1575;
+pub const DISTRIBUTION_DATASET: &str = "distribution";
+
+// The distribution solver follows the policy maximizing the expected score, and propagates the
+// probability mass function of the remaining score instead of its mean. Like the target
+// probability, only the turn start distribution is stored per ScoreState.
+
+pub fn terminal_score_state_distribution(rule_set: RuleSet) -> IntKeyedArrayMap {
+    let terminal_states = ScoreState::get_all_terminal_states(rule_set);
+    let mut terminal_distribution =
+        IntKeyedArrayMap::new(terminal_states.iter(), (1, MAX_SCORE + 1));
+    for terminal_state in terminal_states.iter() {
+        // The only score left is the terminal reward, which is certain
+        let mut distribution = Array2::zeros((1, MAX_SCORE + 1));
+        distribution[[0, terminal_state.terminal_reward(rule_set) as usize]] = 1.0;
+        terminal_distribution.set((*terminal_state).into(), &distribution);
+    }
+    terminal_distribution
+}
+
+pub fn calculate_and_save_all_score_state_distribution(
+    all_dice_states: &[Array1<u8>],
+    transition_function: &TransitionFunction,
+    rule_set: RuleSet,
+    hdf5_file: &hdf5::File,
+) -> Result<(), Error> {
+    // The distribution of the remaining score at the start of the turn of every ScoreState, when
+    // following the policy maximizing the mean
+    reward_evaluation::calculate_and_save_all_turn_start_value(
+        transition_function,
+        rule_set,
+        &Objective::MaximizeMean,
+        DISTRIBUTION_DATASET,
+        terminal_score_state_distribution(rule_set),
+        hdf5_file,
+        |score_state, previous_layer_reward, previous_layer_distribution| {
+            calculate_score_state_distribution(
+                score_state,
+                previous_layer_reward,
+                previous_layer_distribution,
+                all_dice_states,
+                transition_function,
+                rule_set,
+            )
+        },
+    )
+}
+
+pub fn read_distribution(group: &hdf5::Group) -> Result<IntKeyedArrayMap, Error> {
+    // The turn start distribution of every ScoreState of a layer, when maximizing the mean
+    metadata::check_objective_metadata(
+        &group.dataset(DISTRIBUTION_DATASET)?,
+        &Objective::MaximizeMean,
+    )?;
+    IntKeyedArrayMap::read_turn_start_from_group(group, DISTRIBUTION_DATASET)
+}
+
+pub fn calculate_score_state_distribution(
+    score_state: ScoreState,
+    previous_layer_reward: &IntKeyedArrayMap,
+    previous_layer_distribution: &IntKeyedArrayMap,
+    all_dice_states: &[Array1<u8>],
+    transition_function: &TransitionFunction,
+    rule_set: RuleSet,
+) -> Array3<f32> {
+    // Calculate the distribution of the remaining score of a ScoreState, and return it as a
+    // ndarray of shape (num_rolls, num_dice_states, MAX_SCORE + 1).
+    let num_dice_states: usize = all_dice_states.len();
+    let mut score_state_distribution =
+        Array3::<f32>::zeros((rule_set.num_rolls, num_dice_states, MAX_SCORE + 1));
+    // The policy is the one of the expected reward, so the reward is needed to pick the actions
+    let score_state_reward = reward_evaluation::calculate_score_state_reward(
+        score_state,
+        previous_layer_reward,
+        all_dice_states,
+        transition_function,
+        rule_set,
+    );
+
+    // 0 reroll, the distribution is the turn start distribution of the ChildScoreState of the
+    // best ScoreAction, shifted by Reward(ScoreState, DiceState, ScoreAction)
+    for (dice_state_index, dice_state) in all_dice_states.iter().enumerate() {
+        let mut best_action: Option<(f32, u16, ScoreState)> = None;
+        for score_action in score_state.legal_score_actions(dice_state, rule_set) {
+            let action_reward = score_state.reward(score_action, dice_state, rule_set);
+            let child_score_state = score_state
+                .apply_action(score_action, dice_state, rule_set)
+                .expect("legal_score_actions should only return valid actions");
+            let child_reward = previous_layer_reward.get(child_score_state.into()).expect(
+                "previous_layer_reward should contain all ScoreStates reachable from ScoreState",
+            )[[0, 0]];
+            let expected_reward = action_reward as f32 + child_reward;
+            if best_action.is_none_or(|(best_reward, _, _)| expected_reward > best_reward) {
+                best_action = Some((expected_reward, action_reward, child_score_state));
+            }
+        }
+        let (_, action_reward, child_score_state) =
+            best_action.expect("a ScoreState with boxes left should have a legal score action");
+        let child_distribution = previous_layer_distribution
+            .get(child_score_state.into())
+            .expect("previous_layer_distribution should contain all reachable ScoreStates");
+        let action_reward = action_reward as usize;
+        score_state_distribution
+            .slice_mut(s![0, dice_state_index, action_reward..])
+            .assign(&child_distribution.slice(s![0, ..MAX_SCORE + 1 - action_reward]));
+    }
+
+    // 1 and 2 reroll, the distribution is the one of the best KeepAction
+    // Sum of (
+    //   TransitionProbability(KeptState, ToDiceState)
+    //   * Distribution(ScoreState, ToDiceState, Reroll - 1)
+    // ) over all ToDiceStates
+    for reroll in 1..rule_set.num_rolls {
+        let (previous_roll_distribution, mut current_roll_distribution) =
+            score_state_distribution.multi_slice_mut((s![reroll - 1, .., ..], s![reroll, .., ..]));
+        // Every dice state containing a kept state shares its expected reward
+        let kept_reward = transition_function
+            .probability
+            .dot(&score_state_reward.slice(s![reroll - 1, ..]));
+        for dice_state_index in 0..num_dice_states {
+            let best_kept_state_index = transition_function.keep_actions[dice_state_index]
+                .iter()
+                .fold((0, f32::MIN), |best, &kept_state_index| {
+                    if kept_reward[kept_state_index] > best.1 {
+                        (kept_state_index, kept_reward[kept_state_index])
+                    } else {
+                        best
+                    }
+                })
+                .0;
+            current_roll_distribution.row_mut(dice_state_index).assign(
+                &transition_function
+                    .probability
+                    .row_dot_columns(best_kept_state_index, &previous_roll_distribution.view()),
+            );
+        }
+    }
+    score_state_distribution
+}
+
+pub fn mean(distribution: ArrayView1<f32>) -> f32 {
+    distribution
+        .iter()
+        .enumerate()
+        .map(|(score, probability)| score as f32 * probability)
+        .sum()
+}
+
+pub fn variance(distribution: ArrayView1<f32>) -> f32 {
+    let mean = mean(distribution);
+    distribution
+        .iter()
+        .enumerate()
+        .map(|(score, probability)| (score as f32 - mean).powi(2) * probability)
+        .sum()
+}
+
+pub fn quantile(distribution: ArrayView1<f32>, q: f32) -> usize {
+    // The smallest score whose cumulative probability reaches q
+    let mut cumulative_probability = 0.0;
+    for (score, probability) in distribution.iter().enumerate() {
+        cumulative_probability += probability;
+        if cumulative_probability >= q {
+            return score;
+        }
+    }
+    // Rounding errors can leave the total slightly below 1
+    distribution.len() - 1
+}
+
+pub fn median(distribution: ArrayView1<f32>) -> usize {
+    quantile(distribution, 0.5)
+}
+
+pub fn save_distribution_csv(distribution: ArrayView1<f32>, path: &str) -> Result<(), Error> {
+    // One "score,probability" line per score, for plotting
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    writeln!(file, "score,probability")?;
+    for (score, probability) in distribution.iter().enumerate() {
+        writeln!(file, "{},{}", score, probability)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distribution_statistics() {
+        // Probabilities that are exact in f32, so that every statistic is exact too
+        let distribution = array![0.125, 0.375, 0.25, 0.25];
+        assert_eq!(mean(distribution.view()), 1.625);
+        // E[X^2] - E[X]^2 = 3.625 - 2.640625
+        assert_eq!(variance(distribution.view()), 0.984375);
+        // The cumulative probability is exactly 0.5 at score 1
+        assert_eq!(median(distribution.view()), 1);
+        assert_eq!(quantile(distribution.view(), 0.125), 0);
+        assert_eq!(quantile(distribution.view(), 0.6), 2);
+        assert_eq!(quantile(distribution.view(), 0.9), 3);
+
+        // A total slightly below 1 still has a highest quantile
+        let distribution = array![0.5, 0.499];
+        assert_eq!(quantile(distribution.view(), 1.0), 1);
+    }
+}
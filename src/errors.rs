@@ -6,4 +6,31 @@ pub enum Error {
     Io(#[from] std::io::Error),
     #[error("HDF5 error: {0}")]
     Hdf5(#[from] hdf5::Error),
+    #[error("Thread pool error: {0}")]
+    ThreadPool(#[from] rayon::ThreadPoolBuildError),
+    #[error("Missing artifact: {0}")]
+    MissingArtifact(String),
+    #[error("Only {complete_layers} of {num_layers} layers are solved, run solve to resume")]
+    IncompleteTables {
+        complete_layers: usize,
+        num_layers: usize,
+    },
+    #[error("Checkpoint does not match the rule set: {0}")]
+    CheckpointMismatch(String),
+    #[error(
+        "The tables have {attribute} {found} instead of {expected}, rebuild them with this solver"
+    )]
+    MetadataMismatch {
+        attribute: &'static str,
+        expected: String,
+        found: String,
+    },
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+    #[error("Rules file syntax error: {0}")]
+    RulesFileSyntax(#[from] toml::de::Error),
+    #[error("Unknown {kind} in rules file: {name}")]
+    UnknownRuleName { kind: &'static str, name: String },
+    #[error("Invalid rules file: {0}")]
+    InvalidRulesFile(String),
 }
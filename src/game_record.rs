@@ -0,0 +1,164 @@
+use crate::joker_rules::YAHTZEE_BONUS;
+use crate::rule_sets::RuleSet;
+use crate::score_states::{ScoreAction, ScoreState, NUM_SCORE_ACTIONS};
+use ndarray::Array1;
+use std::fmt::Display;
+
+#[derive(Debug, Clone)]
+pub struct GameRecord {
+    // ScoreState only tracks what the solver needs, so the points of every box are kept here
+    score_state: ScoreState,
+    rule_set: RuleSet,
+    box_scores: [Option<u16>; NUM_SCORE_ACTIONS],
+}
+
+impl GameRecord {
+    pub fn new(rule_set: RuleSet) -> Self {
+        Self {
+            score_state: ScoreState::empty(),
+            rule_set,
+            box_scores: [None; NUM_SCORE_ACTIONS],
+        }
+    }
+    pub fn rule_set(&self) -> RuleSet {
+        self.rule_set
+    }
+    pub fn score_state(&self) -> ScoreState {
+        self.score_state
+    }
+    pub fn box_score(&self, score_action: ScoreAction) -> Option<u16> {
+        self.box_scores[score_action as usize]
+    }
+    pub fn is_complete(&self) -> bool {
+        self.rule_set
+            .score_actions()
+            .iter()
+            .all(|&score_action| self.box_score(score_action).is_some())
+    }
+    pub fn apply_action(
+        &mut self,
+        score_action: ScoreAction,
+        dice_state: &Array1<u8>,
+    ) -> Option<u16> {
+        // Record the action on the last roll and return the points it scored, bonus included
+        self.apply_action_on_roll(score_action, dice_state, 0)
+    }
+    pub fn apply_action_on_roll(
+        &mut self,
+        score_action: ScoreAction,
+        dice_state: &Array1<u8>,
+        num_rerolls: usize,
+    ) -> Option<u16> {
+        // Record the action with num_rerolls rerolls left and return the points it scored,
+        // bonus included
+        let child_score_state =
+            self.score_state
+                .apply_action(score_action, dice_state, self.rule_set)?;
+        let reward =
+            self.score_state
+                .reward_on_roll(score_action, dice_state, num_rerolls, self.rule_set);
+        self.box_scores[score_action as usize] =
+            Some(reward - self.rule_set.yahtzee_bonus(&self.score_state, dice_state));
+        self.score_state = child_score_state;
+        Some(reward)
+    }
+    pub fn bank_rerolls(&mut self, num_rerolls: usize) {
+        // Keep the rerolls left after scoring for the next turns, if the rule set banks them
+        self.score_state = self.rule_set.bank_rerolls(self.score_state, num_rerolls);
+    }
+    pub fn upper_sum(&self) -> u16 {
+        ScoreAction::all()
+            .iter()
+            .filter(|score_action| score_action.is_upper())
+            .filter_map(|&score_action| self.box_score(score_action))
+            .sum()
+    }
+    pub fn yahtzee_bonus(&self) -> u16 {
+        self.score_state.yahtzee_bonus_count() * YAHTZEE_BONUS
+    }
+    pub fn banked_reward(&self) -> u16 {
+        // Every reward collected so far, which is the score without the terminal reward
+        self.score() - self.score_state.upper_bonus(self.rule_set)
+    }
+    pub fn score(&self) -> u16 {
+        // Every box, the upper bonus and the Yahtzee bonuses
+        let box_sum: u16 = self.box_scores.iter().flatten().sum();
+        box_sum + self.score_state.upper_bonus(self.rule_set) + self.yahtzee_bonus()
+    }
+}
+
+impl Display for GameRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for &score_action in self.rule_set.score_actions() {
+            match self.box_score(score_action) {
+                Some(box_score) => writeln!(f, "{:?}: {}", score_action, box_score)?,
+                None => writeln!(f, "{:?}: -", score_action)?,
+            }
+            if score_action == ScoreAction::Sixes {
+                writeln!(f, "Upper sum: {}", self.upper_sum())?;
+                writeln!(
+                    f,
+                    "Upper bonus: {}",
+                    self.score_state.upper_bonus(self.rule_set)
+                )?;
+            }
+        }
+        writeln!(f, "Yahtzee bonus: {}", self.yahtzee_bonus())?;
+        write!(f, "Total: {}", self.score())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn test_score() {
+        // A full Yahtzee scorecard with the upper bonus and two bonus Yahtzees
+        let mut game_record = GameRecord::new(RuleSet::default());
+        let sixes = array![0, 0, 0, 0, 0, 5];
+        let fives = array![0, 0, 0, 0, 5, 0];
+        let no_pattern = array![1, 1, 1, 1, 1, 0];
+        assert_eq!(
+            game_record.apply_action(ScoreAction::Yahtzee, &sixes),
+            Some(50)
+        );
+        // The bonus Yahtzees must go in their upper box, the bonus is added to the reward
+        assert_eq!(
+            game_record.apply_action(ScoreAction::Sixes, &sixes),
+            Some(130)
+        );
+        assert_eq!(
+            game_record.apply_action(ScoreAction::Fives, &fives),
+            Some(125)
+        );
+        assert_eq!(game_record.banked_reward(), 305);
+        for (score_action, dice_state, points) in [
+            (ScoreAction::Fours, array![0, 0, 0, 3, 2, 0], 12),
+            (ScoreAction::Ones, no_pattern.clone(), 1),
+            (ScoreAction::Twos, no_pattern.clone(), 2),
+            (ScoreAction::Threes, no_pattern, 3),
+            (ScoreAction::ThreeOfAKind, array![0, 0, 3, 0, 0, 2], 21),
+            (ScoreAction::FourOfAKind, array![0, 0, 0, 0, 1, 4], 29),
+            (ScoreAction::FullHouse, array![0, 2, 3, 0, 0, 0], 25),
+            (ScoreAction::SmallStraight, array![1, 1, 1, 1, 0, 1], 30),
+            (ScoreAction::LargeStraight, array![0, 1, 1, 1, 1, 1], 40),
+            (ScoreAction::Chance, array![0, 0, 0, 0, 3, 2], 27),
+        ] {
+            assert_eq!(
+                game_record.apply_action(score_action, &dice_state),
+                Some(points)
+            );
+        }
+        assert!(game_record.is_complete());
+        assert_eq!(game_record.upper_sum(), 73);
+        assert_eq!(game_record.box_score(ScoreAction::Sixes), Some(30));
+        assert_eq!(game_record.yahtzee_bonus(), 200);
+        // 295 in the boxes, 35 of upper bonus and 200 of Yahtzee bonus
+        assert_eq!(game_record.score(), 530);
+        // The upper bonus is the terminal reward, not collected by any action
+        assert_eq!(game_record.banked_reward(), 495);
+        assert_eq!(game_record.apply_action(ScoreAction::Chance, &sixes), None);
+    }
+}
@@ -0,0 +1,283 @@
+use crate::dice_states::TransitionFunction;
+use crate::joker_rules::YAHTZEE_BONUS;
+use crate::reward_evaluation;
+use crate::rule_sets::RuleSet;
+use crate::score_states::{ScoreAction, ScoreState};
+use ndarray::prelude::*;
+use std::collections::HashMap;
+
+// The head-to-head state is the ScoreState of the player to move, the ScoreState of the opponent
+// and the difference of their banked rewards. Players alternate turns, so the probability of
+// winning from a turn start is one minus the probability of the opponent winning from the next
+// one. Ties count as half a win.
+//
+// The number of ScoreState pairs is the square of the solitaire state space, so nothing is
+// solved ahead of time: pairs are solved on demand and memoized, which is only practical for the
+// last few turns of a game.
+
+pub struct HeadToHeadSolver<'a> {
+    all_dice_states: &'a [Array1<u8>],
+    transition_function: &'a TransitionFunction,
+    first_roll_probability: ArrayView1<'a, f32>,
+    rule_set: RuleSet,
+    // The most points each box can score, without the Yahtzee bonus
+    max_box_reward: Vec<u16>,
+    // The turn start win probability of every (player, opponent) pair solved so far, for every
+    // difference in min_difference(player)..=max_difference(opponent)
+    turn_start_win_probability: HashMap<(u32, u32), Array1<f32>>,
+}
+
+impl<'a> HeadToHeadSolver<'a> {
+    pub fn new(
+        all_dice_states: &'a [Array1<u8>],
+        transition_function: &'a TransitionFunction,
+        rule_set: RuleSet,
+    ) -> Self {
+        let max_box_reward = ScoreAction::all()
+            .iter()
+            .map(|&score_action| {
+                all_dice_states
+                    .iter()
+                    .map(|dice_state| {
+                        ScoreState::empty().reward(score_action, dice_state, rule_set)
+                    })
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        Self {
+            all_dice_states,
+            transition_function,
+            first_roll_probability: transition_function.first_roll_probability(),
+            rule_set,
+            max_box_reward,
+            turn_start_win_probability: HashMap::new(),
+        }
+    }
+
+    fn max_remaining_reward(&self, score_state: ScoreState) -> i32 {
+        // An upper bound of the points left to collect, terminal reward included
+        // Every open box, even those a forced order only allows on later turns
+        let open_score_actions = self
+            .rule_set
+            .score_actions()
+            .iter()
+            .copied()
+            .filter(|&score_action| !score_state.is_taken(score_action))
+            .collect::<Vec<_>>();
+        let box_reward: u16 = open_score_actions
+            .iter()
+            .map(|&score_action| self.max_box_reward[score_action as usize])
+            .sum();
+        let yahtzee_bonus =
+            if !self.rule_set.has_yahtzee_bonus() || score_state.yahtzee() == Some(false) {
+                0
+            } else {
+                YAHTZEE_BONUS * open_score_actions.len() as u16
+            };
+        let upper_bonus = if open_score_actions
+            .iter()
+            .any(|score_action| score_action.is_upper())
+        {
+            self.rule_set.upper_score_bonus()
+        } else {
+            score_state.upper_bonus(self.rule_set)
+        };
+        (box_reward + yahtzee_bonus + upper_bonus) as i32
+    }
+
+    fn min_difference(&self, player: ScoreState) -> i32 {
+        // Below it the player loses even when scoring every point left
+        -self.max_remaining_reward(player)
+    }
+
+    fn max_difference(&self, opponent: ScoreState) -> i32 {
+        // Above it the player wins even when the opponent scores every point left
+        self.max_remaining_reward(opponent)
+    }
+
+    pub fn win_probability(
+        &mut self,
+        player: ScoreState,
+        opponent: ScoreState,
+        difference: i32,
+    ) -> f32 {
+        // The probability of the player to move winning from the start of their turn
+        let min_difference = self.min_difference(player);
+        if difference < min_difference {
+            return 0.0;
+        }
+        if difference > self.max_difference(opponent) {
+            return 1.0;
+        }
+        self.solve(player, opponent);
+        self.turn_start_win_probability[&(player.into(), opponent.into())]
+            [(difference - min_difference) as usize]
+    }
+
+    pub fn score_action_win_probability(
+        &mut self,
+        player: ScoreState,
+        opponent: ScoreState,
+        difference: i32,
+        score_action: ScoreAction,
+        dice_state: &Array1<u8>,
+    ) -> f32 {
+        // The probability of winning after scoring the dice, when the opponent moves next
+        let action_reward = player.reward(score_action, dice_state, self.rule_set) as i32;
+        let child_score_state = player
+            .apply_action(score_action, dice_state, self.rule_set)
+            .expect("score_action should be a legal action of the player");
+        1.0 - self.win_probability(opponent, child_score_state, -(difference + action_reward))
+    }
+
+    pub fn turn_win_probability(
+        &mut self,
+        player: ScoreState,
+        opponent: ScoreState,
+        difference: i32,
+    ) -> Array2<f32> {
+        // The probability of winning of every roll of the turn, as a ndarray of shape
+        // (num_rolls, num_dice_states)
+        let num_dice_states = self.all_dice_states.len();
+        let min_difference = self.min_difference(player);
+        if difference < min_difference {
+            return Array2::zeros((self.rule_set.num_rolls, num_dice_states));
+        }
+        if difference > self.max_difference(opponent) {
+            return Array2::ones((self.rule_set.num_rolls, num_dice_states));
+        }
+        self.calculate_turn_win_probability(player, opponent)
+            .slice(s![.., .., (difference - min_difference) as usize])
+            .to_owned()
+    }
+
+    fn solve(&mut self, player: ScoreState, opponent: ScoreState) {
+        let key = (player.into(), opponent.into());
+        if self.turn_start_win_probability.contains_key(&key) {
+            return;
+        }
+        let min_difference = self.min_difference(player);
+        let max_difference = self.max_difference(opponent);
+        let differences = min_difference..=max_difference;
+        let turn_start_win_probability = if player.possible_score_actions(self.rule_set).is_empty()
+        {
+            if opponent.possible_score_actions(self.rule_set).is_empty() {
+                // The game is over, only the terminal rewards are left
+                let terminal_difference = player.terminal_reward(self.rule_set) as i32
+                    - opponent.terminal_reward(self.rule_set) as i32;
+                differences
+                    .map(
+                        |difference| match (difference + terminal_difference).signum() {
+                            1 => 1.0,
+                            0 => 0.5,
+                            _ => 0.0,
+                        },
+                    )
+                    .collect()
+            } else {
+                // The player is done, the opponent still has to play
+                differences
+                    .map(|difference| 1.0 - self.win_probability(opponent, player, -difference))
+                    .collect()
+            }
+        } else {
+            let turn_win_probability = self.calculate_turn_win_probability(player, opponent);
+            self.first_roll_probability
+                .dot(&turn_win_probability.slice(s![self.rule_set.num_rolls - 1, .., ..]))
+        };
+        self.turn_start_win_probability
+            .insert(key, turn_start_win_probability);
+    }
+
+    fn calculate_turn_win_probability(
+        &mut self,
+        player: ScoreState,
+        opponent: ScoreState,
+    ) -> Array3<f32> {
+        // Calculate the probability of winning of a pair for every difference, and return it as a
+        // ndarray of shape (num_rolls, num_dice_states, num_differences).
+        let all_dice_states = self.all_dice_states;
+        let num_dice_states = all_dice_states.len();
+        let min_difference = self.min_difference(player);
+        let num_differences = (self.max_difference(opponent) - min_difference + 1) as usize;
+        let mut turn_win_probability =
+            Array3::<f32>::zeros((self.rule_set.num_rolls, num_dice_states, num_differences));
+
+        // 0 reroll, the probability is maximized for every difference separately over
+        // 1 - WinProbability(Opponent, ChildScoreState, -(Difference + Reward))
+        for (dice_state_index, dice_state) in all_dice_states.iter().enumerate() {
+            for score_action in player.legal_score_actions(dice_state, self.rule_set) {
+                for difference_index in 0..num_differences {
+                    let difference = min_difference + difference_index as i32;
+                    let probability = self.score_action_win_probability(
+                        player,
+                        opponent,
+                        difference,
+                        score_action,
+                        dice_state,
+                    );
+                    let max_probability =
+                        &mut turn_win_probability[[0, dice_state_index, difference_index]];
+                    *max_probability = max_probability.max(probability);
+                }
+            }
+        }
+
+        // 1 and 2 reroll, same as the expected reward but every difference is maximized
+        // separately over the possible KeepAction
+        reward_evaluation::maximize_over_keep_actions(
+            &mut turn_win_probability,
+            self.transition_function,
+        );
+        turn_win_probability
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dice_states;
+
+    #[test]
+    fn test_one_box_left_win_probability() {
+        // Both players only have the Yahtzee box left and a single roll, so each scores 50 with
+        // probability p = 6 / 6^5 and 0 otherwise
+        let rule_set = RuleSet {
+            num_rolls: 1,
+            ..RuleSet::default()
+        };
+        let all_dice_states = dice_states::get_all_dice_states(rule_set.num_dice());
+        let transition_function = TransitionFunction::new(&all_dice_states);
+        let mut solver = HeadToHeadSolver::new(&all_dice_states, &transition_function, rule_set);
+        let mut score_state = ScoreState::empty();
+        for &score_action in rule_set.score_actions() {
+            if score_action != ScoreAction::Yahtzee {
+                score_state.set_taken(score_action);
+            }
+        }
+        let p = 6.0 / 7776.0;
+        for (difference, expected_win_probability) in [
+            // Symmetric
+            (0, 0.5),
+            // Behind by 30: the player must roll a Yahtzee and the opponent must not
+            (-30, p * (1.0 - p)),
+            // Behind by 50: a Yahtzee against none ties
+            (-50, 0.5 * p * (1.0 - p)),
+            // Ahead by 50: only a Yahtzee of the opponent against none ties
+            (50, 1.0 - 0.5 * p * (1.0 - p)),
+            // Out of reach either way
+            (-51, 0.0),
+            (51, 1.0),
+        ] {
+            let win_probability = solver.win_probability(score_state, score_state, difference);
+            assert!(
+                (win_probability - expected_win_probability).abs() < 1e-5,
+                "difference {}: {} instead of {}",
+                difference,
+                win_probability,
+                expected_win_probability
+            );
+        }
+    }
+}
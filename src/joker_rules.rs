@@ -0,0 +1,223 @@
+use crate::dice_states::NUM_DICES;
+use crate::score_states::{is_yahtzee, ScoreAction, ScoreState};
+use ndarray::Array1;
+
+pub const YAHTZEE_BONUS: u16 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum JokerRule {
+    // Official rule: a Yahtzee rolled after the Yahtzee box is taken must go in the matching
+    // upper box if it is open, else in any open lower box with full points, else in any open
+    // upper box for 0 points
+    #[default]
+    ForcedUpper,
+    // The Yahtzee can go in any open box, lower boxes score full points
+    FreeChoice,
+    // The Yahtzee is never a wild card, lower boxes only score the pattern it makes
+    NoJoker,
+}
+
+impl JokerRule {
+    pub fn parse(argument: &str) -> Option<Self> {
+        match argument {
+            "forced_upper" => Some(Self::ForcedUpper),
+            "free_choice" => Some(Self::FreeChoice),
+            "none" => Some(Self::NoJoker),
+            _ => None,
+        }
+    }
+    pub fn is_joker(&self, score_state: &ScoreState, dice_state: &Array1<u8>) -> bool {
+        // The dice count as any lower box pattern
+        *self != Self::NoJoker && score_state.yahtzee().is_some() && is_yahtzee(dice_state)
+    }
+    pub fn legal_score_actions(
+        &self,
+        score_state: &ScoreState,
+        dice_state: &Array1<u8>,
+        possible_score_actions: Vec<ScoreAction>,
+    ) -> Vec<ScoreAction> {
+        // Restrict the open boxes to the ones the joker rule allows
+        if *self != Self::ForcedUpper || !self.is_joker(score_state, dice_state) {
+            return possible_score_actions;
+        }
+        let face_index = dice_state
+            .iter()
+            .position(|&num_dice| num_dice == NUM_DICES as u8)
+            .expect("a Yahtzee should have all dices on one face");
+        let matching_upper_action = ScoreAction::all()[face_index];
+        if possible_score_actions.contains(&matching_upper_action) {
+            return vec![matching_upper_action];
+        }
+        let lower_score_actions = possible_score_actions
+            .iter()
+            .copied()
+            .filter(|score_action| !score_action.is_upper())
+            .collect::<Vec<_>>();
+        if !lower_score_actions.is_empty() {
+            lower_score_actions
+        } else {
+            possible_score_actions
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rule_sets::{Game, RuleSet};
+    use ndarray::array;
+
+    const LOWER_SCORE_ACTIONS: [ScoreAction; 6] = [
+        ScoreAction::ThreeOfAKind,
+        ScoreAction::FourOfAKind,
+        ScoreAction::FullHouse,
+        ScoreAction::SmallStraight,
+        ScoreAction::LargeStraight,
+        ScoreAction::Chance,
+    ];
+
+    fn yahtzee_taken(yahtzee_field: u8) -> ScoreState {
+        // A scorecard with only the Yahtzee box taken, with 50 points if yahtzee_field > 0
+        let mut score_state = ScoreState::empty();
+        score_state.set_yahtzee_field(yahtzee_field);
+        score_state
+    }
+
+    fn box_points(
+        score_state: &ScoreState,
+        score_action: ScoreAction,
+        dice_state: &Array1<u8>,
+        rule_set: RuleSet,
+    ) -> u16 {
+        // The points written in the box, without the Yahtzee bonus
+        score_state.reward(score_action, dice_state, rule_set)
+            - rule_set.yahtzee_bonus(score_state, dice_state)
+    }
+
+    #[test]
+    fn test_forced_upper_joker() {
+        let rule_set = RuleSet::new(Game::Yahtzee(JokerRule::ForcedUpper));
+        let fours = array![0, 0, 0, 5, 0, 0];
+        let mut score_state = yahtzee_taken(1);
+
+        // The matching upper box is open, it is the only choice
+        assert_eq!(
+            score_state.legal_score_actions(&fours, rule_set),
+            vec![ScoreAction::Fours]
+        );
+        assert_eq!(
+            box_points(&score_state, ScoreAction::Fours, &fours, rule_set),
+            20
+        );
+
+        // Then any open lower box, at full points
+        score_state.set_taken(ScoreAction::Fours);
+        assert_eq!(
+            score_state.legal_score_actions(&fours, rule_set),
+            LOWER_SCORE_ACTIONS.to_vec()
+        );
+        for (score_action, points) in LOWER_SCORE_ACTIONS
+            .into_iter()
+            .zip([20, 20, 25, 30, 40, 20])
+        {
+            assert_eq!(
+                box_points(&score_state, score_action, &fours, rule_set),
+                points
+            );
+        }
+
+        // Then any open upper box, for 0 points
+        for score_action in LOWER_SCORE_ACTIONS {
+            score_state.set_taken(score_action);
+        }
+        let open_upper_score_actions = vec![
+            ScoreAction::Ones,
+            ScoreAction::Twos,
+            ScoreAction::Threes,
+            ScoreAction::Fives,
+            ScoreAction::Sixes,
+        ];
+        assert_eq!(
+            score_state.legal_score_actions(&fours, rule_set),
+            open_upper_score_actions
+        );
+        for score_action in open_upper_score_actions {
+            assert_eq!(box_points(&score_state, score_action, &fours, rule_set), 0);
+        }
+    }
+
+    #[test]
+    fn test_free_choice_and_no_joker() {
+        let fours = array![0, 0, 0, 5, 0, 0];
+        let score_state = yahtzee_taken(1);
+        for (joker_rule, lower_points) in [
+            (JokerRule::FreeChoice, [20, 20, 25, 30, 40, 20]),
+            (JokerRule::NoJoker, [20, 20, 0, 0, 0, 20]),
+        ] {
+            let rule_set = RuleSet::new(Game::Yahtzee(joker_rule));
+            // Every open box is legal, the matching upper box included
+            assert_eq!(
+                score_state.legal_score_actions(&fours, rule_set),
+                score_state.possible_score_actions(rule_set)
+            );
+            assert_eq!(
+                box_points(&score_state, ScoreAction::Fours, &fours, rule_set),
+                20
+            );
+            assert_eq!(
+                box_points(&score_state, ScoreAction::Ones, &fours, rule_set),
+                0
+            );
+            for (score_action, points) in LOWER_SCORE_ACTIONS.into_iter().zip(lower_points) {
+                assert_eq!(
+                    box_points(&score_state, score_action, &fours, rule_set),
+                    points
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_yahtzee_bonus() {
+        let rule_set = RuleSet::default();
+        let fours = array![0, 0, 0, 5, 0, 0];
+        let full_house = array![0, 0, 3, 2, 0, 0];
+
+        // Only paid for a Yahtzee when the Yahtzee box scored 50
+        assert_eq!(rule_set.yahtzee_bonus(&ScoreState::empty(), &fours), 0);
+        assert_eq!(rule_set.yahtzee_bonus(&yahtzee_taken(0), &fours), 0);
+        assert_eq!(rule_set.yahtzee_bonus(&yahtzee_taken(1), &full_house), 0);
+        assert_eq!(
+            rule_set.yahtzee_bonus(&yahtzee_taken(1), &fours),
+            YAHTZEE_BONUS
+        );
+
+        // Every bonus Yahtzee is counted, whichever box it is scored in
+        let score_state = yahtzee_taken(1);
+        assert_eq!(score_state.yahtzee_bonus_count(), 0);
+        let score_state = score_state
+            .apply_action(ScoreAction::Fours, &fours, rule_set)
+            .unwrap();
+        assert_eq!(score_state.yahtzee_bonus_count(), 1);
+        let score_state = score_state
+            .apply_action(ScoreAction::Chance, &fours, rule_set)
+            .unwrap();
+        assert_eq!(score_state.yahtzee_bonus_count(), 2);
+        let score_state = score_state
+            .apply_action(ScoreAction::FullHouse, &full_house, rule_set)
+            .unwrap();
+        assert_eq!(score_state.yahtzee_bonus_count(), 2);
+
+        // A Yahtzee box scored 0 never earns a bonus
+        let score_state = yahtzee_taken(0)
+            .apply_action(ScoreAction::Fours, &fours, rule_set)
+            .unwrap();
+        assert_eq!(score_state.yahtzee_bonus_count(), 0);
+
+        // Nor do rule sets without bonus
+        assert_eq!(
+            RuleSet::new(Game::Yatzy).yahtzee_bonus(&yahtzee_taken(1), &fours),
+            0
+        );
+    }
+}
@@ -1,170 +1,585 @@
+mod advisor;
 mod dice_states;
+mod distribution_evaluation;
+mod game_record;
+mod head_to_head;
+mod joker_rules;
+mod metadata;
+mod multiplayer;
+mod objectives;
+mod rule_files;
+mod rule_sets;
 mod score_states;
-use hdf5;
+mod target_evaluation;
+mod utility_evaluation;
+use dice_states::TransitionFunction;
 use ndarray::prelude::*;
-use std::cmp::Reverse;
+use rayon::prelude::*;
 
-use crate::reward_evaluation::{IntKeyedArrayMap, NUM_ROLLS};
 mod errors;
 mod reward_evaluation;
 
-fn main() -> Result<(), errors::Error> {
-    // If the file already exists, we just load the transition function from it.
-    match hdf5::File::open_rw("/result/yahtzee-solver.h5") {
-        Ok(file) => {
-            let all_dice_states = dice_states::get_all_dice_states();
-            let all_keep_actions = dice_states::get_all_keep_actions();
-
-            let transition_function_dataset = file.dataset("transition_function")?;
-            let transition_function: Array3<f32> = transition_function_dataset.read()?;
-            // Attemp to read the reward of all layers
-            if let Ok(groups) = file.groups() {
-                let mut num_explored_states = 0;
-                let mut layer_groups = groups
-                    .iter()
-                    .filter(|group| group.name().starts_with("/layer_"))
-                    .collect::<Vec<_>>();
-                layer_groups.sort_by_key(|group| {
-                    Reverse(
-                        group
-                            .name()
-                            .split("_")
-                            .last()
-                            .unwrap()
-                            .parse::<u8>()
-                            .unwrap(),
+fn parse_objective() -> Result<objectives::Objective, errors::Error> {
+    // `--target T` maximizes the probability of scoring at least T, and `--utility U` the
+    // expected utility of the score, instead of the expected score
+    let args = std::env::args().collect::<Vec<_>>();
+    if let Some(index) = args.iter().position(|arg| arg == "--target") {
+        let target = args
+            .get(index + 1)
+            .and_then(|target| target.parse::<u16>().ok())
+            .ok_or_else(|| {
+                errors::Error::InvalidArgument("--target expects a score".to_string())
+            })?;
+        return Ok(objectives::Objective::BeatTarget(target));
+    }
+    if let Some(index) = args.iter().position(|arg| arg == "--utility") {
+        let utility = args
+            .get(index + 1)
+            .and_then(|utility| objectives::Utility::parse(utility))
+            .ok_or_else(|| {
+                errors::Error::InvalidArgument(
+                    "--utility expects exponential:A with |A| <= 0.055, quadratic:L with L <= 1/3150 \
+                     or tournament:S=P,S=P,..."
+                        .to_string(),
+                )
+            })?;
+        return Ok(objectives::Objective::MaximizeUtility(utility));
+    }
+    Ok(objectives::Objective::default())
+}
+
+fn parse_rule_set() -> Result<rule_sets::RuleSet, errors::Error> {
+    // `--rules NAME` solves or advises on another rule set than the default Yahtzee,
+    // `--rules-file PATH` on the one defined in a rules file, `--rolls N` plays N rolls per turn
+    // instead of 3 and `--forced-order` fills the boxes top to bottom
+    let args = std::env::args().collect::<Vec<_>>();
+    let mut rule_set = match args.iter().position(|arg| arg == "--rules") {
+        Some(index) => args
+            .get(index + 1)
+            .and_then(|rules| rule_sets::RuleSet::parse(rules))
+            .ok_or_else(|| {
+                errors::Error::InvalidArgument(
+                    "--rules expects yahtzee[-free-choice|-no-joker], yatzy, maxi-yatzy, generala \
+                     or yacht"
+                        .to_string(),
+                )
+            })?,
+        None => rule_sets::RuleSet::default(),
+    };
+    if let Some(index) = args.iter().position(|arg| arg == "--rules-file") {
+        let path = args.get(index + 1).ok_or_else(|| {
+            errors::Error::InvalidArgument("--rules-file expects a path".to_string())
+        })?;
+        rule_set = rule_files::load_rule_set(std::path::Path::new(path))?;
+    }
+    if let Some(index) = args.iter().position(|arg| arg == "--rolls") {
+        rule_set.num_rolls = args
+            .get(index + 1)
+            .and_then(|num_rolls| num_rolls.parse::<usize>().ok())
+            .filter(|&num_rolls| num_rolls >= 1)
+            .ok_or_else(|| {
+                errors::Error::InvalidArgument("--rolls expects at least 1 roll".to_string())
+            })?;
+    }
+    rule_set.forced_order = std::env::args().any(|arg| arg == "--forced-order");
+    Ok(rule_set)
+}
+
+fn set_num_threads() -> Result<(), errors::Error> {
+    // `--threads N` solves on N threads instead of one per core
+    let args = std::env::args().collect::<Vec<_>>();
+    if let Some(index) = args.iter().position(|arg| arg == "--threads") {
+        let num_threads = args
+            .get(index + 1)
+            .and_then(|num_threads| num_threads.parse::<usize>().ok())
+            .filter(|&num_threads| num_threads >= 1)
+            .ok_or_else(|| {
+                errors::Error::InvalidArgument("--threads expects at least 1 thread".to_string())
+            })?;
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .build_global()?;
+    }
+    Ok(())
+}
+
+fn parse_num_players() -> Result<Option<usize>, errors::Error> {
+    // `--players N` advises against N - 1 opponents
+    let args = std::env::args().collect::<Vec<_>>();
+    match args.iter().position(|arg| arg == "--players") {
+        Some(index) => {
+            let num_players = args
+                .get(index + 1)
+                .and_then(|num_players| num_players.parse::<usize>().ok())
+                .filter(|&num_players| num_players >= 2)
+                .ok_or_else(|| {
+                    errors::Error::InvalidArgument(
+                        "--players expects at least 2 players".to_string(),
                     )
-                });
-                for &group in layer_groups.iter() {
-                    let num_states = group.dataset("keys")?.shape().get(0).unwrap().clone();
-                    num_explored_states += num_states;
-                }
-                println!("Number of explored score states: {}", num_explored_states);
-                for &group in layer_groups.iter() {
-                    let key_dataset = group.dataset("keys")?;
-                    let values_dataset = group.dataset("values")?;
-                    let keys: Array1<u32> = key_dataset.read()?;
-                    let values: Array3<f32> = values_dataset.read()?;
-                    let state_reward_map = IntKeyedArrayMap {
-                        keys: keys.to_vec(),
-                        values,
-                    };
-                    println!(
-                        "Number of states in {}: {}",
-                        group.name(),
-                        state_reward_map.keys.len()
-                    );
-                    let mut score_state = score_states::ScoreState::empty();
-                    for num_roll in (0..=NUM_ROLLS).rev() {
-                        println!("Rolls left: {}", num_roll);
-                        println!("Score state: {}", score_state);
-                        print!("Dice state: ");
-                        // Get the dice state in the format: num_1s,num_2s,num_3s,num_4s,num_5s,num_6s
-                        let mut dice_state = String::new();
-                        std::io::stdin().read_line(&mut dice_state)?;
-                        let dice_state = dice_state
-                            .split(',')
-                            .map(|x| x.trim().parse::<u8>().unwrap())
-                            .collect::<Vec<_>>();
-                        let dice_state = array![
-                            dice_state[0],
-                            dice_state[1],
-                            dice_state[2],
-                            dice_state[3],
-                            dice_state[4],
-                            dice_state[5]
-                        ];
-                        if let Some(dice_state_index) =
-                            all_dice_states.iter().position(|x| x == &dice_state)
-                        {
-                            let score_state_reward =
-                                state_reward_map.get(score_state.into()).unwrap();
-                            if num_roll == 0 {
-                                // We want to choose the best score action now instead of the keep action
-                                //let mut best_action;
-                                //let mut best_action_reward;
-                                //for (action_index, action_reward) in
-                                //    score_state.possible_score_actions().iter().enumerate()
-                                //{
-                                //    if let Some(next_state) =
-                                //        score_state.apply_action(*action_reward, &dice_state)
-                                //    {
-                                //        let next_state_reward = score_state_reward.slice(s![2, ..]);
-                                //
-                                //        let action_reward =
-                                //            score_state.reward(*score_action, &dice_state);
-                                //        let child_score_state = score_state
-                                //            .apply_action(*score_action, dice_state)
-                                //            .expect("possible_score_actions should only return valid actions");
-                                //        let child_score_state_index: u32 = child_score_state.into();
-                                //        let all_child_rewards = state_reward_map.get(child_score_state_index).expect(
-                                //            "previous_layer_reward should contain all ScoreStates reachable from ScoreState",
-                                //        );
-                                //        let child_reward: f32 = first_roll_probability
-                                //            .dot(&all_child_rewards.slice(s![2, ..]));
-                                //        max_reward =
-                                //            max_reward.max(action_reward as f32 + child_reward);
-                                //    }
-                                //}
-                            } else {
-                                let next_roll_state_reward =
-                                    score_state_reward.slice(s![num_roll - 1, ..]);
-                                let keep_action_reward: Array2<f32> =
-                                    transition_function.slice(s![dice_state_index, .., ..]).dot(
-                                        &(next_roll_state_reward
-                                            .to_shape((next_roll_state_reward.len(), 1))
-                                            .unwrap()),
-                                    );
-                                let mut keep_action_reward =
-                                    keep_action_reward.iter().enumerate().collect::<Vec<_>>();
-                                keep_action_reward
-                                    .sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap());
-                                // Top 5 keep actions with the highest reward
-                                for (best_action_index, best_action_reward) in
-                                    keep_action_reward.iter().rev()
-                                {
-                                    println!(
-                                        "Reward: {}: Keep action: {}",
-                                        best_action_reward, all_keep_actions[*best_action_index]
-                                    );
-                                }
-                            }
-                        } else {
-                            println!("Invalid dice state");
-                        }
-                    }
-                }
-            } else {
-                // Attempt to stich together the reward of all layers
-                reward_evaluation::calculate_and_save_all_score_state_reward(
+                })?;
+            Ok(Some(num_players))
+        }
+        None => Ok(None),
+    }
+}
+
+fn save_score_state_distribution_once(
+    all_dice_states: &[Array1<u8>],
+    transition_function: &TransitionFunction,
+    rule_set: rule_sets::RuleSet,
+    file: &hdf5::File,
+) -> Result<(), errors::Error> {
+    // The distribution is derived from the explored layers, compute it once
+    distribution_evaluation::calculate_and_save_all_score_state_distribution(
+        all_dice_states,
+        transition_function,
+        rule_set,
+        file,
+    )
+}
+
+fn print_score_distribution(
+    all_dice_states: &[Array1<u8>],
+    transition_function: &TransitionFunction,
+    rule_set: rule_sets::RuleSet,
+    file: &hdf5::File,
+) -> Result<(), errors::Error> {
+    // Summarize the final score distribution of optimal play from an empty scorecard
+    save_score_state_distribution_once(all_dice_states, transition_function, rule_set, file)?;
+    let empty_layer_group =
+        file.group(&rule_set.layer_group_name(rule_set.score_actions().len()))?;
+    let distribution_map = distribution_evaluation::read_distribution(&empty_layer_group)?;
+    let distribution = distribution_map
+        .get(score_states::ScoreState::empty().into())
+        .expect("the first layer should only contain the empty ScoreState");
+    let distribution = distribution.row(0);
+    println!("Mean: {}", distribution_evaluation::mean(distribution));
+    println!("Median: {}", distribution_evaluation::median(distribution));
+    println!(
+        "Standard deviation: {}",
+        distribution_evaluation::variance(distribution).sqrt()
+    );
+    for q in [0.01, 0.1, 0.25, 0.75, 0.9, 0.99] {
+        println!(
+            "Quantile {}: {}",
+            q,
+            distribution_evaluation::quantile(distribution, q)
+        );
+    }
+    distribution_evaluation::save_distribution_csv(distribution, "score_distribution.csv")?;
+    println!("Score distribution saved to score_distribution.csv");
+    Ok(())
+}
+
+fn save_transition_function(file: &hdf5::File, num_dice: usize) -> Result<(), errors::Error> {
+    // Generate the transition function of the number of dice and save it to the file.
+    let all_dice_states = dice_states::get_all_dice_states(num_dice);
+    println!("Number of dice states: {}", all_dice_states.len());
+
+    println!(
+        "Number of kept states: {}",
+        dice_states::get_all_kept_states(num_dice).len()
+    );
+
+    let transition_probability = dice_states::get_transition_probability(&all_dice_states);
+
+    // Save the transition function to a dataset.
+    let transition_function_dataset = file
+        .new_dataset::<f32>()
+        .shape(transition_probability.shape())
+        .create(dice_states::transition_function_name(num_dice).as_str())?;
+    transition_function_dataset.write(&transition_probability)?;
+    metadata::write_transition_function_metadata(&transition_function_dataset, num_dice)?;
+    Ok(())
+}
+
+fn read_expected_score(
+    rule_set: rule_sets::RuleSet,
+    file: &hdf5::File,
+) -> Result<f32, errors::Error> {
+    // The expected final score of optimal play from an empty scorecard
+    let empty_layer_group =
+        file.group(&rule_set.layer_group_name(rule_set.score_actions().len()))?;
+    let reward_map = reward_evaluation::read_turn_start_reward(&empty_layer_group)?;
+    let reward = reward_map
+        .get(score_states::ScoreState::empty().into())
+        .expect("the first layer should only contain the empty ScoreState");
+    Ok(reward[[0, 0]])
+}
+
+fn print_forced_order_report(
+    all_dice_states: &[Array1<u8>],
+    transition_function: &TransitionFunction,
+    rule_set: rule_sets::RuleSet,
+    file: &hdf5::File,
+) -> Result<(), errors::Error> {
+    // Compare the expected score of filling the boxes in order with free choice play, solving
+    // whichever of the two is missing
+    let free_choice_rule_set = rule_sets::RuleSet {
+        forced_order: false,
+        ..rule_set
+    };
+    let forced_order_rule_set = rule_sets::RuleSet {
+        forced_order: true,
+        ..rule_set
+    };
+    let mut expected_scores = Vec::new();
+    for rule_set in [free_choice_rule_set, forced_order_rule_set] {
+        if reward_evaluation::num_complete_layers(file, rule_set)? < rule_set.score_actions().len()
+        {
+            reward_evaluation::calculate_and_save_all_score_state_reward(
+                all_dice_states,
+                transition_function,
+                rule_set,
+                file,
+            )?;
+        }
+        expected_scores.push(read_expected_score(rule_set, file)?);
+    }
+    let (free_choice_score, forced_order_score) = (expected_scores[0], expected_scores[1]);
+    println!("Expected score with free choice: {}", free_choice_score);
+    println!("Expected score with forced order: {}", forced_order_score);
+    println!(
+        "Cost of the forced order: {} points ({}%)",
+        free_choice_score - forced_order_score,
+        100.0 * (free_choice_score - forced_order_score) / free_choice_score
+    );
+    Ok(())
+}
+
+fn print_transition_benchmark(rule_set: rule_sets::RuleSet) {
+    // Time the first layer of the backward solve with the dense and the sparse transition
+    // function, which needs no file since the terminal rewards are known
+    let all_dice_states = dice_states::get_all_dice_states(rule_set.num_dice());
+    let sparse_transition_function = TransitionFunction::new(&all_dice_states);
+    let dense_transition_function = sparse_transition_function.to_dense();
+    println!(
+        "Nonzero transition probabilities: {} of {}",
+        sparse_transition_function.probability.num_nonzeros(),
+        sparse_transition_function.all_kept_states.len() * all_dice_states.len()
+    );
+    let terminal_reward = reward_evaluation::terminal_score_state_reward(rule_set);
+    let mut layer_states = score_states::ScoreState::get_all_terminal_states(rule_set)
+        .iter()
+        .flat_map(|terminal_state| terminal_state.get_parent_states(rule_set))
+        .collect::<Vec<_>>();
+    layer_states.sort_by_key(|&score_state| u32::from(score_state));
+    layer_states.dedup();
+    println!("Solving a layer of {} states", layer_states.len());
+
+    let mut layer_rewards = Vec::new();
+    for (name, transition_function) in [
+        ("dense", &dense_transition_function),
+        ("sparse", &sparse_transition_function),
+    ] {
+        let start = std::time::Instant::now();
+        let layer_reward = layer_states
+            .par_iter()
+            .map(|&score_state| {
+                reward_evaluation::calculate_score_state_reward(
+                    score_state,
+                    &terminal_reward,
                     &all_dice_states,
-                    &all_keep_actions,
-                    &transition_function,
-                    file,
-                )?;
-            }
+                    transition_function,
+                    rule_set,
+                )
+            })
+            .collect::<Vec<_>>();
+        println!(
+            "Layer solve with the {} transition function: {:?}",
+            name,
+            start.elapsed()
+        );
+        layer_rewards.push(layer_reward);
+    }
+    let max_difference = layer_rewards[0]
+        .iter()
+        .zip(layer_rewards[1].iter())
+        .flat_map(|(dense_reward, sparse_reward)| (dense_reward - sparse_reward).into_iter())
+        .fold(0.0, |max: f32, difference| max.max(difference.abs()));
+    println!("Largest reward difference: {}", max_difference);
+}
+
+// The tables file of every subcommand when --tables or --out is not given
+const DEFAULT_TABLES_PATH: &str = "yahtzee-solver.h5";
+
+const USAGE: &str = "usage: yahtzee-solver <build-transitions|solve|advise|info> [--tables PATH] \
+                     [--out PATH] [options]";
+
+enum Command {
+    // Save the transition function of the rule set's dice to --out
+    BuildTransitions,
+    // Solve the expected score tables of the rule set in --out, resuming a partial solve
+    Solve,
+    // Play a game with advice from the tables in --tables
+    Advise,
+    // Summarize the tables in --tables
+    Info,
+}
+
+impl Command {
+    fn parse(argument: &str) -> Option<Self> {
+        match argument {
+            "build-transitions" => Some(Self::BuildTransitions),
+            "solve" => Some(Self::Solve),
+            "advise" => Some(Self::Advise),
+            "info" => Some(Self::Info),
+            _ => None,
+        }
+    }
+}
+
+fn parse_command() -> Result<Command, errors::Error> {
+    std::env::args()
+        .nth(1)
+        .and_then(|command| Command::parse(&command))
+        .ok_or_else(|| errors::Error::InvalidArgument(USAGE.to_string()))
+}
+
+fn parse_tables_path(flag: &str) -> Result<std::path::PathBuf, errors::Error> {
+    // `--tables PATH` is the file read by advise and info, `--out PATH` the one written by
+    // build-transitions and solve
+    let args = std::env::args().collect::<Vec<_>>();
+    match args.iter().position(|arg| arg == flag) {
+        Some(index) => args
+            .get(index + 1)
+            .map(std::path::PathBuf::from)
+            .ok_or_else(|| errors::Error::InvalidArgument(format!("{} expects a path", flag))),
+        None => Ok(std::path::PathBuf::from(DEFAULT_TABLES_PATH)),
+    }
+}
+
+fn open_tables(path: &std::path::Path) -> Result<hdf5::File, errors::Error> {
+    // Every subcommand but build-transitions needs the file to exist
+    if !path.exists() {
+        return Err(errors::Error::MissingArtifact(format!(
+            "{} does not exist, run build-transitions --out {} first",
+            path.display(),
+            path.display()
+        )));
+    }
+    let file = hdf5::File::open_rw(path)?;
+    metadata::check_file_metadata(&file)?;
+    Ok(file)
+}
+
+fn read_transition_function(
+    file: &hdf5::File,
+    path: &std::path::Path,
+    rule_set: rule_sets::RuleSet,
+) -> Result<(Vec<Array1<u8>>, TransitionFunction), errors::Error> {
+    let num_dice = rule_set.num_dice();
+    if !file.link_exists(&dice_states::transition_function_name(num_dice)) {
+        return Err(errors::Error::MissingArtifact(format!(
+            "{} has no transition function for {} dice, run build-transitions --out {} with the \
+             same rules first",
+            path.display(),
+            num_dice,
+            path.display()
+        )));
+    }
+    let all_dice_states = dice_states::get_all_dice_states(num_dice);
+    let transition_function_dataset =
+        file.dataset(&dice_states::transition_function_name(num_dice))?;
+    metadata::check_transition_function_metadata(&transition_function_dataset, num_dice)?;
+    let transition_function =
+        TransitionFunction::with_probability(&all_dice_states, transition_function_dataset.read()?);
+    Ok((all_dice_states, transition_function))
+}
+
+fn build_transitions(rule_set: rule_sets::RuleSet) -> Result<(), errors::Error> {
+    // `--benchmark-transitions` times the sparse transition function instead of saving it
+    if std::env::args().any(|arg| arg == "--benchmark-transitions") {
+        print_transition_benchmark(rule_set);
+        return Ok(());
+    }
+    let path = parse_tables_path("--out")?;
+    let file = if path.exists() {
+        open_tables(&path)?
+    } else {
+        let file = hdf5::File::create(&path)?;
+        metadata::write_file_metadata(&file)?;
+        file
+    };
+    let num_dice = rule_set.num_dice();
+    if file.link_exists(&dice_states::transition_function_name(num_dice)) {
+        println!(
+            "{} already has the transition function for {} dice",
+            path.display(),
+            num_dice
+        );
+        return Ok(());
+    }
+    save_transition_function(&file, num_dice)?;
+    println!("Transition function saved to {}", path.display());
+    Ok(())
+}
+
+fn solve(rule_set: rule_sets::RuleSet) -> Result<(), errors::Error> {
+    let path = parse_tables_path("--out")?;
+    let file = open_tables(&path)?;
+    let (all_dice_states, transition_function) = read_transition_function(&file, &path, rule_set)?;
+    if std::env::args().any(|arg| arg == "--compare-forced-order") {
+        return print_forced_order_report(&all_dice_states, &transition_function, rule_set, &file);
+    }
+    if reward_evaluation::num_complete_layers(&file, rule_set)? == rule_set.score_actions().len() {
+        println!("{} already has every layer of the rule set", path.display());
+        return Ok(());
+    }
+    // Stich together the reward of all layers, from the last checkpoint
+    reward_evaluation::calculate_and_save_all_score_state_reward(
+        &all_dice_states,
+        &transition_function,
+        rule_set,
+        &file,
+    )
+}
+
+fn advise(
+    objective: objectives::Objective,
+    rule_set: rule_sets::RuleSet,
+) -> Result<(), errors::Error> {
+    let path = parse_tables_path("--tables")?;
+    let file = open_tables(&path)?;
+    let (all_dice_states, transition_function) = read_transition_function(&file, &path, rule_set)?;
+    reward_evaluation::check_complete(&file, rule_set)?;
+    metadata::check_rule_set_metadata(&file, rule_set)?;
+    if rule_set.scores_before_last_roll()
+        && (objective != objectives::Objective::MaximizeMean
+            || std::env::args().any(|arg| arg == "--versus")
+            || parse_num_players()?.is_some())
+    {
+        // Only the expected score tables let the dice be scored before the last roll
+        return Err(errors::Error::InvalidArgument(
+            "banked rerolls and served bonuses only support maximizing the expected score"
+                .to_string(),
+        ));
+    }
+    if std::env::args().any(|arg| arg == "--versus") {
+        // Far from the end of the game the advice relies on the opponent's score distribution
+        save_score_state_distribution_once(
+            &all_dice_states,
+            &transition_function,
+            rule_set,
+            &file,
+        )?;
+        return advisor::run_head_to_head_session(
+            &all_dice_states,
+            &transition_function,
+            rule_set,
+            &file,
+        );
+    }
+    if let Some(num_players) = parse_num_players()? {
+        save_score_state_distribution_once(
+            &all_dice_states,
+            &transition_function,
+            rule_set,
+            &file,
+        )?;
+        return advisor::run_multiplayer_session(
+            &all_dice_states,
+            &transition_function,
+            rule_set,
+            num_players,
+            &file,
+        );
+    }
+    // The tables of the other objectives are derived from the explored layers, compute them once
+    match &objective {
+        objectives::Objective::MaximizeMean => {}
+        objectives::Objective::BeatTarget(target) => {
+            target_evaluation::calculate_and_save_all_target_probability(
+                &all_dice_states,
+                &transition_function,
+                rule_set,
+                *target,
+                &file,
+            )?
         }
-        Err(_) => {
-            // The file doesn't exist, so we generate the transition function and save it to the
-            // file.
-
-            let all_dice_states = dice_states::get_all_dice_states();
-            println!("Number of dice states: {}", all_dice_states.len());
-            let all_keep_actions = dice_states::get_all_keep_actions();
-            println!("Number of keep actions: {}", all_keep_actions.len());
-
-            let transition_function =
-                dice_states::get_transition_function(&all_dice_states, &all_keep_actions);
-
-            let file = hdf5::File::create("yahtzee-solver.h5")?;
-
-            // Save the transition function to a dataset.
-            let transition_function_dataset = file
-                .new_dataset::<f32>()
-                .shape(transition_function.shape())
-                .create("transition_function")?;
-            transition_function_dataset.write(&transition_function)?;
+        objectives::Objective::MaximizeUtility(utility) => {
+            utility_evaluation::calculate_and_save_all_utility(
+                &all_dice_states,
+                &transition_function,
+                rule_set,
+                utility,
+                &file,
+            )?
         }
     }
+    advisor::run_session(
+        &all_dice_states,
+        &transition_function,
+        rule_set,
+        &objective,
+        &file,
+    )
+}
+
+fn info(rule_set: rule_sets::RuleSet) -> Result<(), errors::Error> {
+    // `--distribution` also prints the final score distribution, and `--layer-sizes` the
+    // number of states of every layer before and after pruning the unreachable ones
+    let path = parse_tables_path("--tables")?;
+    let file = open_tables(&path)?;
+    let (all_dice_states, transition_function) = read_transition_function(&file, &path, rule_set)?;
+    println!("Tables: {}", path.display());
+    println!("Schema version: {}", metadata::SCHEMA_VERSION);
+    println!("Built by: {}", metadata::read_build_hash(&file)?);
+    println!("Number of dice states: {}", all_dice_states.len());
+    println!(
+        "Number of kept states: {}",
+        transition_function.all_kept_states.len()
+    );
+    let num_layers = rule_set.score_actions().len();
+    let complete_layers = reward_evaluation::num_complete_layers(&file, rule_set)?;
+    if complete_layers > 0 {
+        metadata::check_rule_set_metadata(&file, rule_set)?;
+    }
+    println!("Solved layers: {} of {}", complete_layers, num_layers);
+    let mut num_explored_states = 0;
+    for layer_count in 1..=complete_layers {
+        let group = file.group(&rule_set.layer_group_name(layer_count))?;
+        let num_states = *group.dataset("keys")?.shape().first().unwrap();
+        num_explored_states += num_states;
+    }
+    println!("Number of explored score states: {}", num_explored_states);
+    if std::env::args().any(|arg| arg == "--layer-sizes") {
+        let reachable_states = score_states::ScoreState::get_all_reachable_states(rule_set);
+        let clamped_layer_sizes = reward_evaluation::clamped_layer_sizes(rule_set);
+        for layer_count in 1..=num_layers {
+            println!(
+                "Layer {}: {} states with the upper score clamped, {} of them reachable",
+                layer_count,
+                clamped_layer_sizes[layer_count - 1],
+                reachable_states[num_layers - layer_count].len()
+            );
+        }
+    }
+    if complete_layers < num_layers {
+        return Ok(());
+    }
+    println!("Expected score: {}", read_expected_score(rule_set, &file)?);
+    if std::env::args().any(|arg| arg == "--distribution") {
+        if rule_set.scores_before_last_roll() {
+            return Err(errors::Error::InvalidArgument(
+                "banked rerolls and served bonuses only support maximizing the expected score"
+                    .to_string(),
+            ));
+        }
+        print_score_distribution(&all_dice_states, &transition_function, rule_set, &file)?;
+    }
     Ok(())
 }
+
+fn main() -> Result<(), errors::Error> {
+    let command = parse_command()?;
+    let objective = parse_objective()?;
+    let rule_set = parse_rule_set()?;
+    set_num_threads()?;
+    match command {
+        Command::BuildTransitions => build_transitions(rule_set),
+        Command::Solve => solve(rule_set),
+        Command::Advise => advise(objective, rule_set),
+        Command::Info => info(rule_set),
+    }
+}
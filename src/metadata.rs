@@ -0,0 +1,316 @@
+use crate::dice_states;
+use crate::errors::Error;
+use crate::objectives::Objective;
+use crate::rule_sets::RuleSet;
+use hdf5::types::VarLenUnicode;
+use hdf5::H5Type;
+use ndarray::Array1;
+
+// Bumped whenever the layout of the tables file or the encoding of a ScoreState changes, so that
+// older files are rejected instead of misread
+pub const SCHEMA_VERSION: u32 = 2;
+
+// The commit the solver was built from, set by build.rs
+pub const BUILD_HASH: &str = env!("YAHTZEE_SOLVER_BUILD_HASH");
+
+// The tables file describes itself with attributes at four places:
+// - the root: the schema version and the build that created the file
+// - every transition function: its number of dice and the order of its rows and columns
+// - the group of every rule set: the rules its layers were solved for, including the number of
+//   dice
+// - every table of the layers: the objective it was computed for, the distribution being the one
+//   of the policy maximizing the mean
+// The build hash is informational, every other attribute has to match the running solver.
+
+fn to_unicode(value: &str) -> VarLenUnicode {
+    value
+        .parse()
+        .expect("metadata strings should not contain NUL characters")
+}
+
+fn write_attribute<T: H5Type>(
+    location: &hdf5::Location,
+    name: &str,
+    value: &T,
+) -> Result<(), Error> {
+    location.new_attr::<T>().create(name)?.write_scalar(value)?;
+    Ok(())
+}
+
+fn check_attribute<T: H5Type + PartialEq + std::fmt::Display>(
+    location: &hdf5::Location,
+    name: &'static str,
+    expected: T,
+) -> Result<(), Error> {
+    let found = if location
+        .attr_names()?
+        .iter()
+        .any(|attr_name| attr_name == name)
+    {
+        Some(location.attr(name)?.read_scalar::<T>()?)
+    } else {
+        None
+    };
+    if found.as_ref() == Some(&expected) {
+        return Ok(());
+    }
+    Err(Error::MetadataMismatch {
+        attribute: name,
+        expected: expected.to_string(),
+        found: found.map_or_else(|| "missing".to_string(), |found| found.to_string()),
+    })
+}
+
+fn ordering_fingerprint(states: &[Array1<u8>]) -> u64 {
+    // FNV-1a of the dice of every state in order, with the length of the state as a separator
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for state in states {
+        for &byte in std::iter::once(&(state.len() as u8)).chain(state.iter()) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+pub fn write_file_metadata(hdf5_file: &hdf5::File) -> Result<(), Error> {
+    write_attribute(hdf5_file, "schema_version", &SCHEMA_VERSION)?;
+    write_attribute(hdf5_file, "build_hash", &to_unicode(BUILD_HASH))
+}
+
+pub fn check_file_metadata(hdf5_file: &hdf5::File) -> Result<(), Error> {
+    check_attribute(hdf5_file, "schema_version", SCHEMA_VERSION)
+}
+
+pub fn read_build_hash(hdf5_file: &hdf5::File) -> Result<String, Error> {
+    Ok(hdf5_file
+        .attr("build_hash")?
+        .read_scalar::<VarLenUnicode>()?
+        .to_string())
+}
+
+pub fn write_transition_function_metadata(
+    dataset: &hdf5::Dataset,
+    num_dice: usize,
+) -> Result<(), Error> {
+    write_attribute(dataset, "num_dice", &(num_dice as u32))?;
+    write_attribute(
+        dataset,
+        "dice_state_ordering",
+        &ordering_fingerprint(&dice_states::get_all_dice_states(num_dice)),
+    )?;
+    write_attribute(
+        dataset,
+        "keep_action_ordering",
+        &ordering_fingerprint(&dice_states::get_all_kept_states(num_dice)),
+    )
+}
+
+pub fn check_transition_function_metadata(
+    dataset: &hdf5::Dataset,
+    num_dice: usize,
+) -> Result<(), Error> {
+    // The rows and columns of the matrix are only meaningful in the order they were saved in
+    check_attribute(dataset, "num_dice", num_dice as u32)?;
+    check_attribute(
+        dataset,
+        "dice_state_ordering",
+        ordering_fingerprint(&dice_states::get_all_dice_states(num_dice)),
+    )?;
+    check_attribute(
+        dataset,
+        "keep_action_ordering",
+        ordering_fingerprint(&dice_states::get_all_kept_states(num_dice)),
+    )
+}
+
+fn rule_set_group(hdf5_file: &hdf5::File, rule_set: RuleSet) -> Result<hdf5::Group, Error> {
+    // The default rule set lives at the root, like its layers
+    Ok(match rule_set.namespace().as_str() {
+        "" => hdf5_file.group("/")?,
+        namespace => hdf5_file.group(namespace)?,
+    })
+}
+
+pub fn save_rule_set_metadata(hdf5_file: &hdf5::File, rule_set: RuleSet) -> Result<(), Error> {
+    // Record the rules on the first solve, and check them when resuming one
+    let group = rule_set_group(hdf5_file, rule_set)?;
+    if group
+        .attr_names()?
+        .iter()
+        .any(|attr_name| attr_name == "rule_variant")
+    {
+        return check_rule_set_metadata(hdf5_file, rule_set);
+    }
+    write_attribute(
+        &group,
+        "rule_variant",
+        &to_unicode(&format!("{:?}", rule_set.game)),
+    )?;
+    write_attribute(&group, "num_dice", &(rule_set.num_dice() as u32))?;
+    write_attribute(&group, "num_rolls", &(rule_set.num_rolls as u32))?;
+    write_attribute(&group, "forced_order", &rule_set.forced_order)
+}
+
+pub fn check_rule_set_metadata(hdf5_file: &hdf5::File, rule_set: RuleSet) -> Result<(), Error> {
+    let group = rule_set_group(hdf5_file, rule_set)?;
+    check_attribute(
+        &group,
+        "rule_variant",
+        to_unicode(&format!("{:?}", rule_set.game)),
+    )?;
+    check_attribute(&group, "num_dice", rule_set.num_dice() as u32)?;
+    check_attribute(&group, "num_rolls", rule_set.num_rolls as u32)?;
+    check_attribute(&group, "forced_order", rule_set.forced_order)
+}
+
+pub fn write_objective_metadata(
+    dataset: &hdf5::Dataset,
+    objective: &Objective,
+) -> Result<(), Error> {
+    write_attribute(
+        dataset,
+        "objective",
+        &to_unicode(&format!("{:?}", objective)),
+    )
+}
+
+pub fn check_objective_metadata(
+    dataset: &hdf5::Dataset,
+    objective: &Objective,
+) -> Result<(), Error> {
+    // Tables computed for another objective would advise for it without any error
+    check_attribute(
+        dataset,
+        "objective",
+        to_unicode(&format!("{:?}", objective)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "yahtzee-solver-metadata-{}-{}.h5",
+            name,
+            std::process::id()
+        ))
+    }
+
+    fn assert_mismatch(result: Result<(), Error>, expected_attribute: &str, expected_found: &str) {
+        match result {
+            Err(Error::MetadataMismatch {
+                attribute, found, ..
+            }) => {
+                assert_eq!(attribute, expected_attribute);
+                assert_eq!(found, expected_found);
+            }
+            result => panic!(
+                "expected a mismatch of {}, got {:?}",
+                expected_attribute, result
+            ),
+        }
+    }
+
+    #[test]
+    fn test_file_metadata() -> Result<(), Error> {
+        let hdf5_file = hdf5::File::create(temp_path("file"))?;
+        assert_mismatch(check_file_metadata(&hdf5_file), "schema_version", "missing");
+        write_attribute(&hdf5_file, "schema_version", &(SCHEMA_VERSION - 1))?;
+        assert_mismatch(
+            check_file_metadata(&hdf5_file),
+            "schema_version",
+            &(SCHEMA_VERSION - 1).to_string(),
+        );
+
+        let hdf5_file = hdf5::File::create(temp_path("new_file"))?;
+        write_file_metadata(&hdf5_file)?;
+        check_file_metadata(&hdf5_file)?;
+        std::fs::remove_file(temp_path("file"))?;
+        std::fs::remove_file(temp_path("new_file"))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_transition_function_metadata() -> Result<(), Error> {
+        let hdf5_file = hdf5::File::create(temp_path("transition_function"))?;
+        let dataset = hdf5_file
+            .new_dataset::<f32>()
+            .shape((1,))
+            .create("missing")?;
+        assert_mismatch(
+            check_transition_function_metadata(&dataset, 5),
+            "num_dice",
+            "missing",
+        );
+        let dataset = hdf5_file
+            .new_dataset::<f32>()
+            .shape((1,))
+            .create("five_dice")?;
+        write_transition_function_metadata(&dataset, 5)?;
+        check_transition_function_metadata(&dataset, 5)?;
+        assert_mismatch(
+            check_transition_function_metadata(&dataset, 6),
+            "num_dice",
+            "5",
+        );
+        std::fs::remove_file(temp_path("transition_function"))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_rule_set_metadata() -> Result<(), Error> {
+        let hdf5_file = hdf5::File::create(temp_path("rule_set"))?;
+        let rule_set = RuleSet::default();
+        assert_mismatch(
+            check_rule_set_metadata(&hdf5_file, rule_set),
+            "rule_variant",
+            "missing",
+        );
+        save_rule_set_metadata(&hdf5_file, rule_set)?;
+        check_rule_set_metadata(&hdf5_file, rule_set)?;
+        let forced_order_rule_set = RuleSet {
+            forced_order: true,
+            ..rule_set
+        };
+        assert_mismatch(
+            check_rule_set_metadata(&hdf5_file, forced_order_rule_set),
+            "forced_order",
+            "false",
+        );
+        // Resuming a solve of other rules is refused too
+        assert_mismatch(
+            save_rule_set_metadata(&hdf5_file, forced_order_rule_set),
+            "forced_order",
+            "false",
+        );
+        std::fs::remove_file(temp_path("rule_set"))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_objective_metadata() -> Result<(), Error> {
+        let hdf5_file = hdf5::File::create(temp_path("objective"))?;
+        let dataset = hdf5_file
+            .new_dataset::<f32>()
+            .shape((1,))
+            .create("beat_200")?;
+        assert_mismatch(
+            check_objective_metadata(&dataset, &Objective::BeatTarget(200)),
+            "objective",
+            "missing",
+        );
+        write_objective_metadata(&dataset, &Objective::BeatTarget(200))?;
+        check_objective_metadata(&dataset, &Objective::BeatTarget(200))?;
+        assert_mismatch(
+            check_objective_metadata(&dataset, &Objective::BeatTarget(250)),
+            "objective",
+            "BeatTarget(200)",
+        );
+        std::fs::remove_file(temp_path("objective"))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+use crate::dice_states::TransitionFunction;
+use crate::distribution_evaluation::{self, MAX_SCORE};
+use crate::errors::Error;
+use crate::reward_evaluation::{self, IntKeyedArrayMap};
+use crate::rule_sets::RuleSet;
+use crate::score_states::{ScoreAction, ScoreState};
+use ndarray::prelude::*;
+
+// With three or more players the exact state is every scorecard at once, which is far out of
+// reach. The heuristic assumes every player keeps maximizing their own expected score, so each
+// final score follows the single-player distribution tables. The player then maximizes the
+// probability of finishing first over the current turn only, given those distributions for the
+// opponents and for their own remaining turns.
+
+pub fn read_layer_distribution(
+    hdf5_file: &hdf5::File,
+    num_boxes_left: usize,
+    rule_set: RuleSet,
+) -> Result<Option<IntKeyedArrayMap>, Error> {
+    // Layer 0 (terminal) is not stored, its distribution is only the terminal reward
+    if num_boxes_left == 0 {
+        return Ok(None);
+    }
+    let layer_group = hdf5_file.group(&rule_set.layer_group_name(num_boxes_left))?;
+    Ok(Some(distribution_evaluation::read_distribution(
+        &layer_group,
+    )?))
+}
+
+pub fn final_score_distribution(
+    layer_distribution: Option<&IntKeyedArrayMap>,
+    score_state: ScoreState,
+    banked_reward: u16,
+    rule_set: RuleSet,
+) -> Array1<f32> {
+    // The distribution of the final score of a player at the start of their turn, as a ndarray
+    // of shape (MAX_SCORE + 1)
+    let banked_reward = banked_reward as usize;
+    let mut final_score_distribution = Array1::zeros(MAX_SCORE + 1);
+    match layer_distribution {
+        Some(layer_distribution) => {
+            let remaining_distribution = layer_distribution
+                .get(score_state.into())
+                .expect("layer_distribution should contain all ScoreStates of the layer");
+            final_score_distribution
+                .slice_mut(s![banked_reward..])
+                .assign(&remaining_distribution.slice(s![0, ..MAX_SCORE + 1 - banked_reward]));
+        }
+        None => {
+            final_score_distribution
+                [banked_reward + score_state.terminal_reward(rule_set) as usize] = 1.0;
+        }
+    }
+    final_score_distribution
+}
+
+pub fn first_place_probability(opponent_distributions: &[Array1<f32>]) -> Array1<f32> {
+    // The probability of finishing first with every final score, as a ndarray of shape
+    // (MAX_SCORE + 1). Opponents are independent, and a tie with one of them counts as half.
+    let mut first_place_probability = Array1::ones(MAX_SCORE + 1);
+    for opponent_distribution in opponent_distributions {
+        let mut opponent_below = 0.0;
+        for (score, probability) in first_place_probability.iter_mut().enumerate() {
+            *probability *= opponent_below + 0.5 * opponent_distribution[score];
+            opponent_below += opponent_distribution[score];
+        }
+    }
+    first_place_probability
+}
+
+pub fn score_action_first_place_probability(
+    score_state: ScoreState,
+    banked_reward: u16,
+    next_layer_distribution: Option<&IntKeyedArrayMap>,
+    first_place_probability: ArrayView1<f32>,
+    score_action: ScoreAction,
+    dice_state: &Array1<u8>,
+    rule_set: RuleSet,
+) -> f32 {
+    // The probability of finishing first after scoring the dice, when the remaining turns keep
+    // maximizing the expected score
+    let action_reward = score_state.reward(score_action, dice_state, rule_set);
+    let child_score_state = score_state
+        .apply_action(score_action, dice_state, rule_set)
+        .expect("score_action should be a legal action of the ScoreState");
+    final_score_distribution(
+        next_layer_distribution,
+        child_score_state,
+        banked_reward + action_reward,
+        rule_set,
+    )
+    .dot(&first_place_probability)
+}
+
+pub fn calculate_score_state_first_place_probability(
+    score_state: ScoreState,
+    banked_reward: u16,
+    next_layer_distribution: Option<&IntKeyedArrayMap>,
+    first_place_probability: ArrayView1<f32>,
+    all_dice_states: &[Array1<u8>],
+    transition_function: &TransitionFunction,
+    rule_set: RuleSet,
+) -> Array2<f32> {
+    // Calculate the probability of finishing first of every roll of the turn, and return it as a
+    // ndarray of shape (num_rolls, num_dice_states).
+    let num_dice_states: usize = all_dice_states.len();
+    let mut score_state_probability =
+        Array3::<f32>::zeros((rule_set.num_rolls, num_dice_states, 1));
+
+    // 0 reroll, maximize over the possible ScoreAction
+    for (dice_state_index, dice_state) in all_dice_states.iter().enumerate() {
+        score_state_probability[[0, dice_state_index, 0]] = score_state
+            .legal_score_actions(dice_state, rule_set)
+            .into_iter()
+            .map(|score_action| {
+                score_action_first_place_probability(
+                    score_state,
+                    banked_reward,
+                    next_layer_distribution,
+                    first_place_probability,
+                    score_action,
+                    dice_state,
+                    rule_set,
+                )
+            })
+            .fold(0.0, f32::max);
+    }
+
+    // 1 and 2 reroll, same as the expected reward
+    reward_evaluation::maximize_over_keep_actions(
+        &mut score_state_probability,
+        transition_function,
+    );
+    score_state_probability.remove_axis(Axis(2))
+}
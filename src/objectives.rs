@@ -0,0 +1,188 @@
+use crate::distribution_evaluation::MAX_SCORE;
+use crate::reward_evaluation::TURN_START_REWARD_DATASET;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Objective {
+    // Maximize the expected final score
+    #[default]
+    MaximizeMean,
+    // Maximize the probability of finishing with at least the target score
+    BeatTarget(u16),
+    // Maximize the expected utility of the final score
+    MaximizeUtility(Utility),
+}
+
+impl Objective {
+    pub fn dataset_name(&self) -> String {
+        // Name of the dataset holding the tables of the objective in every layer_N group
+        match self {
+            Self::MaximizeMean => TURN_START_REWARD_DATASET.to_string(),
+            Self::BeatTarget(target) => format!("beat_{}", target),
+            Self::MaximizeUtility(utility) => format!("utility_{}", utility.name()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Utility {
+    // u(x) = (1 - exp(-a x)) / a, risk averse for a > 0 and risk seeking for a < 0. f32 only tells
+    // scores apart while exp(-|a| MAX_SCORE) is a normal number, which bounds |a| to about 0.055.
+    Exponential(f32),
+    // u(x) = x - λ x², so that E[u] = mean - λ (variance + mean²). It only increases with the
+    // score up to MAX_SCORE for λ <= 1 / (2 MAX_SCORE), risk averse for λ > 0 and risk seeking
+    // for λ < 0.
+    Quadratic(f32),
+    // Piecewise linear payoff through the (score, payoff) breakpoints sorted by score, flat
+    // before the first and after the last one
+    Tournament(Vec<(u16, f32)>),
+}
+
+impl Utility {
+    pub fn parse(argument: &str) -> Option<Self> {
+        // exponential:A, quadratic:λ or tournament:SCORE=PAYOFF,SCORE=PAYOFF,...
+        let (name, parameters) = argument.split_once(':')?;
+        match name {
+            "exponential" => Some(Self::Exponential(parameters.parse().ok().filter(
+                |risk_aversion: &f32| {
+                    risk_aversion.abs() * MAX_SCORE as f32 <= -f32::MIN_POSITIVE.ln()
+                },
+            )?)),
+            "quadratic" => {
+                Some(Self::Quadratic(parameters.parse().ok().filter(
+                    |tradeoff: &f32| *tradeoff <= 1.0 / (2 * MAX_SCORE) as f32,
+                )?))
+            }
+            "tournament" => {
+                let mut breakpoints = parameters
+                    .split(',')
+                    .map(|breakpoint| {
+                        let (score, payoff) = breakpoint.split_once('=')?;
+                        Some((score.trim().parse().ok()?, payoff.trim().parse().ok()?))
+                    })
+                    .collect::<Option<Vec<(u16, f32)>>>()?;
+                breakpoints.sort_by_key(|(score, _)| *score);
+                Some(Self::Tournament(breakpoints))
+            }
+            _ => None,
+        }
+    }
+    pub fn name(&self) -> String {
+        // The utility and its parameters, so that every utility gets its own tables
+        match self {
+            Self::Exponential(risk_aversion) => format!("exponential_{}", risk_aversion),
+            Self::Quadratic(tradeoff) => format!("quadratic_{}", tradeoff),
+            Self::Tournament(breakpoints) => format!(
+                "tournament_{}",
+                breakpoints
+                    .iter()
+                    .map(|(score, payoff)| format!("{}={}", score, payoff))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+        }
+    }
+    pub fn utility(&self, score: usize) -> f32 {
+        let score = score as f32;
+        match self {
+            Self::Exponential(risk_aversion) => {
+                if *risk_aversion == 0.0 {
+                    return score;
+                }
+                // An affine transformation of (1 - exp(-a x)) / a keeps the same preferences.
+                // Shifting the exponent into [-|a| MAX_SCORE, 0] keeps exp from overflowing, and
+                // from rounding 1 - exp(-a x) to the same value for every high score.
+                let shift = if *risk_aversion > 0.0 {
+                    0.0
+                } else {
+                    MAX_SCORE as f32
+                };
+                -(-risk_aversion * (score - shift)).exp() / risk_aversion
+            }
+            Self::Quadratic(tradeoff) => score - tradeoff * score * score,
+            Self::Tournament(breakpoints) => {
+                let (first_score, first_payoff) = *breakpoints
+                    .first()
+                    .expect("a tournament payoff should have at least one breakpoint");
+                if score <= first_score as f32 {
+                    return first_payoff;
+                }
+                for window in breakpoints.windows(2) {
+                    let ((low_score, low_payoff), (high_score, high_payoff)) =
+                        (window[0], window[1]);
+                    if score <= high_score as f32 {
+                        let t = (score - low_score as f32) / (high_score - low_score) as f32;
+                        return low_payoff + t * (high_payoff - low_payoff);
+                    }
+                }
+                breakpoints[breakpoints.len() - 1].1
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tournament_utility() {
+        let utility = Utility::parse("tournament:300=1,200=0,250=0.75").unwrap();
+        assert_eq!(
+            utility,
+            Utility::Tournament(vec![(200, 0.0), (250, 0.75), (300, 1.0)])
+        );
+        // Flat before the first and after the last breakpoint
+        assert_eq!(utility.utility(0), 0.0);
+        assert_eq!(utility.utility(200), 0.0);
+        assert_eq!(utility.utility(300), 1.0);
+        assert_eq!(utility.utility(MAX_SCORE), 1.0);
+        // Linear between two breakpoints
+        assert_eq!(utility.utility(210), 0.15);
+        assert_eq!(utility.utility(250), 0.75);
+        assert_eq!(utility.utility(275), 0.875);
+    }
+
+    #[test]
+    fn test_exponential_utility() {
+        // Without risk aversion the utility is the score
+        let utility = Utility::parse("exponential:0").unwrap();
+        assert_eq!(utility.utility(0), 0.0);
+        assert_eq!(utility.utility(250), 250.0);
+
+        // Up to the constant 1 / a, u(x) = -exp(-a x) / a
+        let utility = Utility::parse("exponential:0.01").unwrap();
+        assert_eq!(utility.utility(0), -100.0);
+        assert!((utility.utility(100) + (-1.0f32).exp() / 0.01).abs() < 1e-3);
+
+        // Every utility is finite and strictly increasing, both over realistic scores and at the
+        // ends of the score range
+        for risk_aversion in ["0.01", "0.05", "-0.01", "-0.05"] {
+            let utility = Utility::parse(&format!("exponential:{}", risk_aversion)).unwrap();
+            let utilities = (0..=MAX_SCORE)
+                .map(|score| utility.utility(score))
+                .collect::<Vec<_>>();
+            assert!(utilities.iter().all(|utility| utility.is_finite()));
+            assert!(utilities.windows(2).all(|window| window[0] <= window[1]));
+            assert!((100..=500)
+                .step_by(50)
+                .collect::<Vec<_>>()
+                .windows(2)
+                .all(|window| utilities[window[0]] < utilities[window[1]]));
+            assert!(utilities[0] < utilities[1]);
+            assert!(utilities[MAX_SCORE - 1] < utilities[MAX_SCORE]);
+        }
+        // Beyond |a| = 0.055 realistic scores would all get the same utility
+        for risk_aversion in ["0.1", "-0.1", "-1", "inf"] {
+            assert!(Utility::parse(&format!("exponential:{}", risk_aversion)).is_none());
+        }
+    }
+
+    #[test]
+    fn test_quadratic_utility() {
+        let utility = Utility::parse("quadratic:0.0001").unwrap();
+        assert_eq!(utility.utility(100), 99.0);
+        // Above 1 / (2 MAX_SCORE) high scores would be worth less than lower ones
+        assert!(Utility::parse("quadratic:0.001").is_none());
+        assert!(Utility::parse("quadratic:-0.001").is_some());
+    }
+}
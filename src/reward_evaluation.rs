@@ -1,10 +1,15 @@
+use crate::dice_states::TransitionFunction;
 use crate::errors::Error;
-use crate::score_states::ScoreState;
+use crate::metadata;
+use crate::objectives::Objective;
+use crate::rule_sets::RuleSet;
+use crate::score_states::{ScoreState, MAX_BANKED_REROLLS};
 use ndarray::prelude::*;
 use rayon::prelude::*;
-use std::collections::HashSet;
-use std::sync::{Arc, Mutex};
-pub const NUM_ROLLS: usize = 3;
+
+// The expected reward of every ScoreState before its first roll, saved in every layer
+pub const TURN_START_REWARD_DATASET: &str = "expected_reward";
+
 #[derive(Debug, Clone)]
 pub struct IntKeyedArrayMap {
     pub keys: Vec<u32>,
@@ -26,13 +31,26 @@ impl IntKeyedArrayMap {
         }
     }
 
-    pub fn get(&self, key: u32) -> Option<ArrayView2<f32>> {
+    pub fn get(&self, key: u32) -> Option<ArrayView2<'_, f32>> {
         match self.keys.binary_search(&key) {
             Ok(index) => Some(self.values.slice(s![index, .., ..])),
             Err(_) => None,
         }
     }
 
+    pub fn read_turn_start_from_group(
+        group: &hdf5::Group,
+        dataset_name: &str,
+    ) -> Result<Self, Error> {
+        // Turn start tables only have one row per key, stored as a 2D dataset
+        let keys: Array1<u32> = group.dataset("keys")?.read()?;
+        let values: Array2<f32> = group.dataset(dataset_name)?.read()?;
+        Ok(Self {
+            keys: keys.to_vec(),
+            values: values.insert_axis(Axis(1)),
+        })
+    }
+
     pub fn set(&mut self, key: u32, value: &Array2<f32>) {
         match self.keys.binary_search(&key) {
             Ok(index) => {
@@ -45,162 +63,769 @@ impl IntKeyedArrayMap {
     }
 }
 
+pub fn table_num_rolls(rule_set: RuleSet) -> usize {
+    // The number of rows of a ScoreState table, one per number of rerolls left. Banked rerolls
+    // add rows past the rolls of a turn.
+    if rule_set.has_banked_rerolls() {
+        rule_set.num_rolls + MAX_BANKED_REROLLS as usize
+    } else {
+        rule_set.num_rolls
+    }
+}
+
+pub fn turn_start_rerolls(score_state: ScoreState, rule_set: RuleSet) -> usize {
+    // The row of the first roll of a turn
+    rule_set.num_rolls - 1 + score_state.banked_rerolls() as usize
+}
+
+pub fn terminal_score_state_reward(rule_set: RuleSet) -> IntKeyedArrayMap {
+    // The turn start reward of every terminal state, the game is over so the only reward left is
+    // the terminal reward
+    let terminal_states = ScoreState::get_all_terminal_states(rule_set);
+    let mut terminal_reward_map = IntKeyedArrayMap::new(terminal_states.iter(), (1, 1));
+    for terminal_state in terminal_states.iter() {
+        let terminal_reward =
+            Array2::from_elem((1, 1), terminal_state.terminal_reward(rule_set) as f32);
+        terminal_reward_map.set((*terminal_state).into(), &terminal_reward);
+    }
+    terminal_reward_map
+}
+
+pub fn read_turn_start_reward(group: &hdf5::Group) -> Result<IntKeyedArrayMap, Error> {
+    // The turn start reward of every ScoreState of a layer, one value per key
+    metadata::check_objective_metadata(
+        &group.dataset(TURN_START_REWARD_DATASET)?,
+        &Objective::MaximizeMean,
+    )?;
+    IntKeyedArrayMap::read_turn_start_from_group(group, TURN_START_REWARD_DATASET)
+}
+
+fn layer_turn_start_reward(
+    layer_reward: &IntKeyedArrayMap,
+    first_roll_probability: ArrayView1<f32>,
+    rule_set: RuleSet,
+) -> Array2<f32> {
+    // The expected reward of every ScoreState of a layer before its first roll, of shape
+    // (num_keys, 1)
+    Array2::from_shape_fn((layer_reward.keys.len(), 1), |(index, _)| {
+        let turn_start_rerolls = turn_start_rerolls(layer_reward.keys[index].into(), rule_set);
+        first_roll_probability.dot(&layer_reward.values.slice(s![index, turn_start_rerolls, ..]))
+    })
+}
+
 pub fn calculate_and_save_all_score_state_reward(
     all_dice_states: &[Array1<u8>],
-    all_keep_actions: &[Array1<bool>],
-    transition_function: &Array3<f32>,
-    hdf5_file: hdf5::File,
+    transition_function: &TransitionFunction,
+    rule_set: RuleSet,
+    hdf5_file: &hdf5::File,
 ) -> Result<(), Error> {
     // Calculate the reward of all ScoreStates, and return it as a ndarray
-    // of shape (NUM_SCORE_STATES, NUM_ROLLS, num_dice_states).
-    let num_dice_states: usize = all_dice_states.len();
+    // of shape (NUM_SCORE_STATES, table_num_rolls, num_dice_states).
+    calculate_and_save_score_state_reward(
+        all_dice_states,
+        transition_function,
+        rule_set,
+        rule_set.score_actions().len(),
+        hdf5_file,
+    )
+}
 
-    let terminal_states = ScoreState::get_all_terminal_states();
+fn calculate_and_save_score_state_reward(
+    all_dice_states: &[Array1<u8>],
+    transition_function: &TransitionFunction,
+    rule_set: RuleSet,
+    last_layer: usize,
+    hdf5_file: &hdf5::File,
+) -> Result<(), Error> {
+    // Solve and save the layers from the last checkpoint up to last_layer included
+    // Only the states reachable from the empty scorecard are solved, layer N holding the ones
+    // with N boxes left to fill
+    let reachable_states = ScoreState::get_all_reachable_states(rule_set);
+    let num_layers = rule_set.score_actions().len();
+    for layer_count in 1..=num_layers {
+        println!(
+            "Layer {}: {} reachable states",
+            layer_count,
+            reachable_states[num_layers - layer_count].len()
+        );
+    }
+    // The layers of other rule sets live in their own group, next to the transition function
+    let namespace = rule_set.namespace();
+    if !namespace.is_empty() && !hdf5_file.link_exists(&namespace) {
+        hdf5_file.create_group(&namespace)?;
+    }
+    // A partial solve of other rules must not be resumed
+    metadata::save_rule_set_metadata(hdf5_file, rule_set)?;
 
-    let mut exploration_set = HashSet::new();
-    for terminal_state in terminal_states.iter() {
-        let parent_states = terminal_state.get_parent_states();
-        exploration_set.extend(parent_states.into_iter());
+    // Resume after the last complete layer, whose turn start reward is all the next one needs
+    let complete_layers = num_complete_layers(hdf5_file, rule_set)?;
+    let mut previous_layer_reward = if complete_layers > 0 {
+        println!("Resuming after layer {}", complete_layers);
+        read_turn_start_reward(&hdf5_file.group(&rule_set.layer_group_name(complete_layers))?)?
+    } else {
+        terminal_score_state_reward(rule_set)
+    };
+    let checkpoint_group_name = rule_set.checkpoint_group_name();
+    if complete_layers < num_layers && hdf5_file.link_exists(&checkpoint_group_name) {
+        let checkpoint_group = hdf5_file.group(&checkpoint_group_name)?;
+        if checkpoint_group.link_exists("next_keys") {
+            let next_keys: Array1<u32> = checkpoint_group.dataset("next_keys")?.read()?;
+            let reachable_keys = reachable_states[num_layers - complete_layers - 1]
+                .iter()
+                .map(|&score_state| u32::from(score_state))
+                .collect::<Vec<_>>();
+            if next_keys.to_vec() != reachable_keys {
+                return Err(Error::CheckpointMismatch(format!(
+                    "layer {} was saved with {} states instead of {}",
+                    complete_layers + 1,
+                    next_keys.len(),
+                    reachable_keys.len()
+                )));
+            }
+        }
     }
-    let mut previous_layer_reward =
-        IntKeyedArrayMap::new(terminal_states.iter(), (NUM_ROLLS, num_dice_states));
 
-    let mut layer_count = 0;
-    while exploration_set.len() > 0 {
-        layer_count += 1;
-        let exploration_states: Vec<ScoreState> = exploration_set.into_iter().collect();
+    for layer_count in complete_layers + 1..=last_layer {
+        let exploration_states = &reachable_states[num_layers - layer_count];
         println!(
             "Exploring layer {} with {} states...",
             layer_count,
             exploration_states.len()
         );
-        let current_layer_reward = Arc::new(Mutex::new(IntKeyedArrayMap::new(
-            exploration_states.iter(),
-            (NUM_ROLLS, num_dice_states),
-        )));
-        let mut next_exploration_set = HashSet::new();
-        next_exploration_set.par_extend(exploration_states.par_chunks(50000).flat_map(
-            |score_states| {
-                let result = score_states
-                    .into_iter()
-                    .map(|score_state| {
-                        (
-                            *score_state,
-                            calculate_score_state_reward(
-                                *score_state,
-                                &previous_layer_reward,
-                                all_dice_states,
-                                all_keep_actions,
-                                transition_function,
-                            ),
-                        )
-                    })
-                    .collect::<Vec<(ScoreState, Array2<f32>)>>();
-                let mut parent_states = HashSet::new();
-                if let Ok(mut current_layer_reward) = current_layer_reward.lock() {
-                    for (score_state, reward) in result.iter() {
-                        current_layer_reward.set((*score_state).into(), &reward);
-                        parent_states.extend(score_state.get_parent_states());
-                    }
-                } else {
-                    panic!("current_layer_reward lock failed");
-                }
-                parent_states
-            },
-        ));
+        let current_layer_reward = solve_layer(
+            exploration_states,
+            &previous_layer_reward,
+            all_dice_states,
+            transition_function,
+            rule_set,
+        );
 
-        // Save the previous layer to the hdf5_file as two datasets in a group.
-        let current_layer_reward = Arc::try_unwrap(current_layer_reward)
-            .expect("current_layer_reward should only have one owner")
-            .into_inner()
-            .expect("current_layer_reward should not be locked");
-        // Save the current layer to the hdf5_file as two datasets in a group.
-        let current_layer_group = hdf5_file.create_group(&format!("layer_{}", layer_count))?;
+        // Save the current layer to the hdf5_file as datasets in a group. The next layer and the
+        // advisor only need the turn start reward, the advisor recalculates the rolls of a turn.
+        let turn_start_reward = layer_turn_start_reward(
+            &current_layer_reward,
+            transition_function.first_roll_probability(),
+            rule_set,
+        );
+        if hdf5_file.link_exists(&rule_set.layer_group_name(layer_count)) {
+            // Left over by an interrupted solve
+            hdf5_file.unlink(&rule_set.layer_group_name(layer_count))?;
+        }
+        let current_layer_group =
+            hdf5_file.create_group(&rule_set.layer_group_name(layer_count))?;
         let current_layer_keys_dataset = current_layer_group
             .new_dataset::<u32>()
             .shape((current_layer_reward.keys.len(),))
             .create("keys")?;
         current_layer_keys_dataset.write(&current_layer_reward.keys)?;
-        let current_layer_values_dataset = current_layer_group
+        let turn_start_reward_dataset = current_layer_group
             .new_dataset::<f32>()
-            .shape(current_layer_reward.values.shape())
-            .create("values")?;
-        current_layer_values_dataset.write(&current_layer_reward.values)?;
+            .shape(turn_start_reward.shape())
+            .create(TURN_START_REWARD_DATASET)?;
+        turn_start_reward_dataset.write(&turn_start_reward)?;
+        metadata::write_objective_metadata(&turn_start_reward_dataset, &Objective::MaximizeMean)?;
+
+        let next_keys = match layer_count {
+            layer_count if layer_count < num_layers => reachable_states
+                [num_layers - layer_count - 1]
+                .iter()
+                .map(|&score_state| u32::from(score_state))
+                .collect(),
+            _ => Vec::new(),
+        };
+        save_checkpoint(hdf5_file, rule_set, layer_count, &next_keys)?;
+
+        previous_layer_reward = IntKeyedArrayMap {
+            keys: current_layer_reward.keys,
+            values: turn_start_reward.insert_axis(Axis(1)),
+        };
+    }
+
+    Ok(())
+}
+
+fn solve_layer(
+    exploration_states: &[ScoreState],
+    previous_layer_reward: &IntKeyedArrayMap,
+    all_dice_states: &[Array1<u8>],
+    transition_function: &TransitionFunction,
+    rule_set: RuleSet,
+) -> IntKeyedArrayMap {
+    // The reward of every ScoreState of a layer, from the turn start reward of the next one.
+    // Every key owns the row at its index, so the workers never write to the same rows.
+    let mut layer_reward = IntKeyedArrayMap::new(
+        exploration_states.iter(),
+        (table_num_rolls(rule_set), all_dice_states.len()),
+    );
+    let IntKeyedArrayMap { keys, values } = &mut layer_reward;
+    values
+        .outer_iter_mut()
+        .into_par_iter()
+        .zip(keys.par_iter())
+        .for_each(|(mut reward, &key)| {
+            reward.assign(&calculate_score_state_reward(
+                ScoreState::from(key),
+                previous_layer_reward,
+                all_dice_states,
+                transition_function,
+                rule_set,
+            ));
+        });
+    layer_reward
+}
 
-        previous_layer_reward = current_layer_reward;
-        exploration_set = next_exploration_set;
+pub fn num_complete_layers(hdf5_file: &hdf5::File, rule_set: RuleSet) -> Result<usize, Error> {
+    // The number of layers saved by the solve so far
+    let checkpoint_group_name = rule_set.checkpoint_group_name();
+    if hdf5_file.link_exists(&checkpoint_group_name) {
+        let complete_layers: Array1<u32> = hdf5_file
+            .group(&checkpoint_group_name)?
+            .dataset("complete_layers")?
+            .read()?;
+        return Ok(complete_layers[0] as usize);
+    }
+    Ok(0)
+}
+
+pub fn check_complete(hdf5_file: &hdf5::File, rule_set: RuleSet) -> Result<(), Error> {
+    // Advising from a partial solve would read missing layers
+    let complete_layers = num_complete_layers(hdf5_file, rule_set)?;
+    let num_layers = rule_set.score_actions().len();
+    if complete_layers < num_layers {
+        return Err(Error::IncompleteTables {
+            complete_layers,
+            num_layers,
+        });
+    }
+    Ok(())
+}
+
+pub fn calculate_and_save_all_turn_start_value<F>(
+    transition_function: &TransitionFunction,
+    rule_set: RuleSet,
+    objective: &Objective,
+    dataset_name: &str,
+    terminal_value: IntKeyedArrayMap,
+    hdf5_file: &hdf5::File,
+    calculate_score_state_value: F,
+) -> Result<(), Error>
+where
+    F: Fn(ScoreState, &IntKeyedArrayMap, &IntKeyedArrayMap) -> Array3<f32> + Sync,
+{
+    // Walk the layers explored by calculate_and_save_all_score_state_reward from the last turn to
+    // the first, and save the turn start value of every ScoreState next to its expected reward.
+    // calculate_score_state_value gets the ScoreState, the turn start reward and the turn start
+    // value of the next layer, and returns the value of every roll of the turn.
+    // The layers are written from the first to the last, so only the last one tells the tables
+    // are complete. An interrupted derivation is redone from the first layer.
+    let num_layers = rule_set.score_actions().len();
+    let dataset_path = |layer_count| {
+        format!(
+            "{}/{}",
+            rule_set.layer_group_name(layer_count),
+            dataset_name
+        )
+    };
+    if hdf5_file.link_exists(&dataset_path(num_layers)) {
+        return Ok(());
+    }
+    for layer_count in 1..num_layers {
+        if hdf5_file.link_exists(&dataset_path(layer_count)) {
+            hdf5_file.unlink(&dataset_path(layer_count))?;
+        }
+    }
+    let first_roll_probability = transition_function.first_roll_probability();
+    let mut previous_layer_reward = terminal_score_state_reward(rule_set);
+    let mut previous_layer_value = terminal_value;
+    let mut layer_count = 1;
+    while hdf5_file.link_exists(&rule_set.layer_group_name(layer_count)) {
+        let layer_group = hdf5_file.group(&rule_set.layer_group_name(layer_count))?;
+        let keys: Array1<u32> = layer_group.dataset("keys")?.read()?;
+        println!(
+            "Calculating {} of layer {} with {} states...",
+            dataset_name,
+            layer_count,
+            keys.len()
+        );
+        let turn_start_value = keys
+            .to_vec()
+            .into_par_iter()
+            .map(|key| {
+                let score_state_value = calculate_score_state_value(
+                    ScoreState::from(key),
+                    &previous_layer_reward,
+                    &previous_layer_value,
+                );
+                first_roll_probability.dot(&score_state_value.slice(s![
+                    rule_set.num_rolls - 1,
+                    ..,
+                    ..
+                ]))
+            })
+            .collect::<Vec<Array1<f32>>>();
+        let turn_start_value = ndarray::stack(
+            Axis(0),
+            &turn_start_value
+                .iter()
+                .map(|x| x.view())
+                .collect::<Vec<_>>(),
+        )
+        .expect("every turn start value of a layer should have the same length");
+
+        let current_layer_dataset = layer_group
+            .new_dataset::<f32>()
+            .shape(turn_start_value.shape())
+            .create(dataset_name)?;
+        current_layer_dataset.write(&turn_start_value)?;
+        metadata::write_objective_metadata(&current_layer_dataset, objective)?;
+
+        previous_layer_reward = read_turn_start_reward(&layer_group)?;
+        previous_layer_value = IntKeyedArrayMap {
+            keys: keys.to_vec(),
+            values: turn_start_value.insert_axis(Axis(1)),
+        };
+        layer_count += 1;
     }
 
     Ok(())
 }
 
-fn calculate_score_state_reward(
+fn save_checkpoint(
+    hdf5_file: &hdf5::File,
+    rule_set: RuleSet,
+    complete_layers: usize,
+    next_keys: &[u32],
+) -> Result<(), Error> {
+    // Record a complete layer along with the states of the next one. The layer is written
+    // before, so an interruption at any point leaves the checkpoint of the previous layer.
+    let checkpoint_group_name = rule_set.checkpoint_group_name();
+    let checkpoint_group = if hdf5_file.link_exists(&checkpoint_group_name) {
+        hdf5_file.group(&checkpoint_group_name)?
+    } else {
+        let checkpoint_group = hdf5_file.create_group(&checkpoint_group_name)?;
+        checkpoint_group
+            .new_dataset::<u32>()
+            .shape((1,))
+            .create("complete_layers")?
+            .write(&[0u32])?;
+        checkpoint_group
+    };
+    if checkpoint_group.link_exists("next_keys") {
+        checkpoint_group.unlink("next_keys")?;
+    }
+    if !next_keys.is_empty() {
+        let next_keys_dataset = checkpoint_group
+            .new_dataset::<u32>()
+            .shape((next_keys.len(),))
+            .create("next_keys")?;
+        next_keys_dataset.write(next_keys)?;
+    }
+    checkpoint_group
+        .dataset("complete_layers")?
+        .write(&[complete_layers as u32])?;
+    hdf5_file.flush()?;
+    Ok(())
+}
+
+pub fn clamped_layer_sizes(rule_set: RuleSet) -> Vec<usize> {
+    // The number of states of every layer when exploring the parents of every terminal state,
+    // reachable or not. The upper score is already clamped, so this is less than the states
+    // solved before the clamp. Enumerating them costs as much as the reachable states, so only
+    // the info subcommand reports them.
+    let mut layer_sizes = Vec::new();
+    let mut exploration_states = ScoreState::get_all_terminal_states(rule_set);
+    loop {
+        let mut parent_states = exploration_states
+            .par_iter()
+            .flat_map_iter(|score_state| score_state.get_parent_states(rule_set))
+            .map(u32::from)
+            .collect::<Vec<_>>();
+        if parent_states.is_empty() {
+            return layer_sizes;
+        }
+        parent_states.par_sort_unstable();
+        parent_states.dedup();
+        layer_sizes.push(parent_states.len());
+        exploration_states = parent_states.into_iter().map(ScoreState::from).collect();
+    }
+}
+
+pub fn calculate_score_state_reward(
     score_state: ScoreState,
     previous_layer_reward: &IntKeyedArrayMap,
     all_dice_states: &[Array1<u8>],
-    all_keep_actions: &[Array1<bool>],
-    transition_function: &Array3<f32>,
+    transition_function: &TransitionFunction,
+    rule_set: RuleSet,
 ) -> Array2<f32> {
     // Calculate the reward of a ScoreState, and return it as a ndarray
-    // of shape (NUM_ROLLS, num_dice_states).
+    // of shape (table_num_rolls, num_dice_states). previous_layer_reward only holds the turn
+    // start reward of the next layer.
     let num_dice_states: usize = all_dice_states.len();
-    let num_keep_actions: usize = all_keep_actions.len();
-    let mut score_state_reward = Array2::zeros((NUM_ROLLS, num_dice_states));
-
-    let keep_none_action = array![false, false, false, false, false];
-    let keep_none_action_index = all_keep_actions
-        .iter()
-        .position(|x| x == keep_none_action)
-        .expect("all_keep_actions should contain keep_none_action");
-
-    // The probablity of rolling any state by rerolling all dices
-    let first_roll_probability: ArrayView1<f32> =
-        transition_function.slice(s![0, keep_none_action_index, ..]);
+    let mut score_state_reward = Array2::zeros((table_num_rolls(rule_set), num_dice_states));
+
     // 0 reroll, the reward is the
     // Reward(ScoreState, DiceState, ScoreAction)
-    // + Sum of (
-    //   ProbFirstRoll * Reward(ChildScoreState, FirstRollDiceState, Reroll=2)
-    // ) over all first roll dice states
+    // + TurnStartReward(ChildScoreState)
     // Maximize over the possible actions to get
     // Reward(ScoreState, DiceState, Reroll=0)
-    let score_actions = score_state.possible_score_actions();
     for (dice_state_index, dice_state) in all_dice_states.iter().enumerate() {
-        let mut max_reward: f32 = 0.0;
-        for score_action in score_actions.iter() {
-            let action_reward = score_state.reward(*score_action, dice_state);
-            let child_score_state = score_state
-                .apply_action(*score_action, dice_state)
-                .expect("possible_score_actions should only return valid actions");
-            let child_score_state_index: u32 = child_score_state.into();
-            let all_child_rewards = previous_layer_reward.get(child_score_state_index).expect(
-                "previous_layer_reward should contain all ScoreStates reachable from ScoreState",
-            );
-            let child_reward: f32 = first_roll_probability.dot(&all_child_rewards.slice(s![2, ..]));
-            max_reward = max_reward.max(action_reward as f32 + child_reward);
-        }
-        score_state_reward[[0, dice_state_index]] = max_reward;
+        score_state_reward[[0, dice_state_index]] =
+            score_now_reward(score_state, dice_state, 0, previous_layer_reward, rule_set);
     }
 
     // 1 and 2 reroll, the reward is the
     // Sum of (
-    //   TransitionProbability(DiceState, KeepAction, ToDiceState)
+    //   TransitionProbability(KeptState, ToDiceState)
     //   * Reward(ScoreState, ToDiceState, Reroll - 1)
     // ) over all ToDiceStates
 
-    // Maximize over the possible KeepAction to get
+    // Maximize over the KeptState of the possible KeepAction to get
     // Reward(ScoreState, DiceState, Reroll)
-    for reroll in 1..NUM_ROLLS {
-        for dice_state_index in 0..num_dice_states {
-            let mut max_reward: f32 = 0.0;
-            for keep_action_index in 0..num_keep_actions {
-                let keep_probability: ArrayView1<f32> =
-                    transition_function.slice(s![dice_state_index, keep_action_index, ..]);
-                let keep_reward =
-                    keep_probability.dot(&score_state_reward.slice(s![reroll - 1, ..]));
-                max_reward = max_reward.max(keep_reward)
+    for reroll in 1..=turn_start_rerolls(score_state, rule_set) {
+        // Every dice state containing a kept state shares its reward
+        let kept_reward = transition_function
+            .probability
+            .dot(&score_state_reward.slice(s![reroll - 1, ..]));
+        for (dice_state_index, dice_state) in all_dice_states.iter().enumerate() {
+            let mut max_reward = transition_function.keep_actions[dice_state_index]
+                .iter()
+                .map(|&kept_state_index| kept_reward[kept_state_index])
+                .fold(0.0, f32::max);
+            if rule_set.scores_before_last_roll() {
+                // Scoring before the last roll banks the rerolls left for the next turns, or
+                // collects the served bonus of the first roll
+                max_reward = max_reward.max(score_now_reward(
+                    score_state,
+                    dice_state,
+                    reroll,
+                    previous_layer_reward,
+                    rule_set,
+                ));
             }
             score_state_reward[[reroll, dice_state_index]] = max_reward;
         }
     }
     score_state_reward
 }
+
+fn score_now_reward(
+    score_state: ScoreState,
+    dice_state: &Array1<u8>,
+    num_rerolls: usize,
+    previous_layer_reward: &IntKeyedArrayMap,
+    rule_set: RuleSet,
+) -> f32 {
+    // The best reward of scoring the dice with num_rerolls rerolls left
+    let mut max_reward: f32 = 0.0;
+    for score_action in score_state.legal_score_actions(dice_state, rule_set) {
+        let action_reward =
+            score_state.reward_on_roll(score_action, dice_state, num_rerolls, rule_set);
+        let child_score_state = rule_set.bank_rerolls(
+            score_state
+                .apply_action(score_action, dice_state, rule_set)
+                .expect("legal_score_actions should only return valid actions"),
+            num_rerolls,
+        );
+        let child_reward = previous_layer_reward.get(child_score_state.into()).expect(
+            "previous_layer_reward should contain all ScoreStates reachable from ScoreState",
+        )[[0, 0]];
+        max_reward = max_reward.max(action_reward as f32 + child_reward);
+    }
+    max_reward
+}
+
+pub fn maximize_over_keep_actions(
+    score_state_table: &mut Array3<f32>,
+    transition_function: &TransitionFunction,
+) {
+    // Fill the rerolls of a table of shape (num_rolls, num_dice_states, num_columns) from
+    // its 0 reroll, where every column is an independent objective maximized separately over
+    // the possible KeepAction
+    for reroll in 1..score_state_table.len_of(Axis(0)) {
+        let (previous_roll_table, mut current_roll_table) =
+            score_state_table.multi_slice_mut((s![reroll - 1, .., ..], s![reroll, .., ..]));
+        let kept_value = transition_function
+            .probability
+            .dot_columns(&previous_roll_table.view());
+        for (dice_state_index, mut max_value) in current_roll_table.outer_iter_mut().enumerate() {
+            max_value.fill(f32::MIN);
+            for &kept_state_index in transition_function.keep_actions[dice_state_index].iter() {
+                max_value.zip_mut_with(&kept_value.row(kept_state_index), |x, &y| *x = x.max(y));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dice_states;
+    use crate::rule_files;
+    use crate::rule_sets::Game;
+    use std::collections::HashMap;
+
+    fn unclamped_turn_start_reward(
+        score_state: ScoreState,
+        turn_start_reward: &mut HashMap<u32, f32>,
+        all_dice_states: &[Array1<u8>],
+        transition_function: &TransitionFunction,
+        rule_set: RuleSet,
+    ) -> f32 {
+        // The turn start reward when the upper score is never clamped, every child solved on
+        // demand instead of from the reachable states
+        if let Some(&reward) = turn_start_reward.get(&score_state.into()) {
+            return reward;
+        }
+        if score_state.possible_score_actions(rule_set).is_empty() {
+            return score_state.terminal_reward(rule_set) as f32;
+        }
+        let mut score_state_reward =
+            Array3::<f32>::zeros((rule_set.num_rolls, all_dice_states.len(), 1));
+        for (dice_state_index, dice_state) in all_dice_states.iter().enumerate() {
+            for score_action in score_state.legal_score_actions(dice_state, rule_set) {
+                let mut child_score_state = score_state
+                    .apply_action(score_action, dice_state, rule_set)
+                    .unwrap();
+                if score_action.is_upper() {
+                    child_score_state.set_upper_score(
+                        score_state.upper_score()
+                            + dice_state[score_action as usize] * (score_action as u8 + 1),
+                    );
+                }
+                let reward = score_state.reward(score_action, dice_state, rule_set) as f32
+                    + unclamped_turn_start_reward(
+                        child_score_state,
+                        turn_start_reward,
+                        all_dice_states,
+                        transition_function,
+                        rule_set,
+                    );
+                let max_reward = &mut score_state_reward[[0, dice_state_index, 0]];
+                *max_reward = max_reward.max(reward);
+            }
+        }
+        maximize_over_keep_actions(&mut score_state_reward, transition_function);
+        let reward = transition_function
+            .first_roll_probability()
+            .dot(&score_state_reward.slice(s![rule_set.num_rolls - 1, .., 0]));
+        turn_start_reward.insert(score_state.into(), reward);
+        reward
+    }
+
+    #[test]
+    fn test_clamped_and_pruned_reward() {
+        // The last two layers solved from the reachable states with the upper score clamped give
+        // the same turn start reward as without the clamp or the pruning
+        let rule_set = RuleSet::default();
+        let all_dice_states = dice_states::get_all_dice_states(rule_set.num_dice());
+        let transition_function = TransitionFunction::new(&all_dice_states);
+        let reachable_states = ScoreState::get_all_reachable_states(rule_set);
+        let num_layers = rule_set.score_actions().len();
+
+        let mut previous_layer_reward = terminal_score_state_reward(rule_set);
+        let mut unclamped_reward = HashMap::new();
+        for layer_count in 1..=2 {
+            let layer_reward = solve_layer(
+                &reachable_states[num_layers - layer_count],
+                &previous_layer_reward,
+                &all_dice_states,
+                &transition_function,
+                rule_set,
+            );
+            let turn_start_reward = layer_turn_start_reward(
+                &layer_reward,
+                transition_function.first_roll_probability(),
+                rule_set,
+            );
+            // The clamped upper score is also a valid unclamped one. Every fourth state keeps the
+            // test short.
+            for (&key, reward) in layer_reward
+                .keys
+                .iter()
+                .zip(turn_start_reward.column(0))
+                .step_by(4)
+            {
+                let expected_reward = unclamped_turn_start_reward(
+                    ScoreState::from(key),
+                    &mut unclamped_reward,
+                    &all_dice_states,
+                    &transition_function,
+                    rule_set,
+                );
+                assert!(
+                    (reward - expected_reward).abs() < 1e-3,
+                    "{}: {} instead of {}",
+                    ScoreState::from(key),
+                    reward,
+                    expected_reward
+                );
+            }
+            previous_layer_reward = IntKeyedArrayMap {
+                keys: layer_reward.keys,
+                values: turn_start_reward.insert_axis(Axis(1)),
+            };
+        }
+        // Some of the unclamped children are above the bonus threshold
+        assert!(unclamped_reward
+            .keys()
+            .any(|&key| ScoreState::from(key).upper_score() > rule_set.upper_score_threshold()));
+    }
+
+    fn brute_force_keep_value(
+        dice_state: &Array1<u8>,
+        previous_roll_value: ArrayView1<f32>,
+        all_dice_states: &[Array1<u8>],
+    ) -> f32 {
+        // The best value after rerolling, over every subset of the dice, with every roll of the
+        // rerolled dice enumerated one by one
+        let dice = (0..6)
+            .flat_map(|face| std::iter::repeat_n(face, dice_state[face] as usize))
+            .collect::<Vec<_>>();
+        let mut max_value = f32::MIN;
+        for mask in 0..1 << dice.len() {
+            let mut kept_state = Array1::<u8>::zeros(6);
+            for (index, &face) in dice.iter().enumerate() {
+                if mask & (1 << index) != 0 {
+                    kept_state[face] += 1;
+                }
+            }
+            let num_rerolled = dice.len() - kept_state.sum() as usize;
+            let num_rolls = 6usize.pow(num_rerolled as u32);
+            let mut value = 0.0;
+            for roll in 0..num_rolls {
+                let mut next_dice_state = kept_state.clone();
+                let mut rest = roll;
+                for _ in 0..num_rerolled {
+                    next_dice_state[rest % 6] += 1;
+                    rest /= 6;
+                }
+                let next_index = all_dice_states
+                    .iter()
+                    .position(|state| *state == next_dice_state)
+                    .unwrap();
+                value += previous_roll_value[next_index] / num_rolls as f32;
+            }
+            max_value = max_value.max(value);
+        }
+        max_value
+    }
+
+    #[test]
+    fn test_keep_actions_match_every_subset() {
+        // Maximizing over the distinct kept states gives the same value as over the 32 subsets
+        // of the dice
+        let all_dice_states = dice_states::get_all_dice_states(dice_states::NUM_DICES);
+        let transition_function = TransitionFunction::new(&all_dice_states);
+        let mut table = Array3::from_shape_fn((2, all_dice_states.len(), 2), |(_, i, j)| {
+            ((i * 37 + j * 11) % 101) as f32
+        });
+        maximize_over_keep_actions(&mut table, &transition_function);
+        for dice_state_index in (0..all_dice_states.len()).step_by(25) {
+            for column in 0..2 {
+                let brute_force_value = brute_force_keep_value(
+                    &all_dice_states[dice_state_index],
+                    table.slice(s![0, .., column]),
+                    &all_dice_states,
+                );
+                assert!(
+                    (table[[1, dice_state_index, column]] - brute_force_value).abs() < 1e-3,
+                    "dice state {}: {} instead of {}",
+                    all_dice_states[dice_state_index],
+                    table[[1, dice_state_index, column]],
+                    brute_force_value
+                );
+            }
+        }
+    }
+
+    fn read_all_turn_start_reward(
+        hdf5_file: &hdf5::File,
+        rule_set: RuleSet,
+    ) -> Result<Vec<IntKeyedArrayMap>, Error> {
+        (1..=rule_set.score_actions().len())
+            .map(|layer_count| {
+                IntKeyedArrayMap::read_turn_start_from_group(
+                    &hdf5_file.group(&rule_set.layer_group_name(layer_count))?,
+                    TURN_START_REWARD_DATASET,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_resume() -> Result<(), Error> {
+        // A solve stopped after some layers is refused by the advisor, and resuming it gives the
+        // same tables as an uninterrupted one
+        let custom_rules = rule_files::parse_custom_rules(
+            r#"
+            name = "resume_test"
+            [upper_bonus]
+            threshold = 18
+            points = 10
+            [[box]]
+            name = "sixes"
+            [[box]]
+            name = "chance"
+            points = "sum"
+            [[box]]
+            name = "yahtzee"
+            pattern = "yahtzee"
+            points = 50
+            "#,
+        )?;
+        let rule_set = RuleSet::new(Game::Custom(Box::leak(Box::new(custom_rules))));
+        let num_layers = rule_set.score_actions().len();
+        let all_dice_states = dice_states::get_all_dice_states(rule_set.num_dice());
+        let transition_function = TransitionFunction::new(&all_dice_states);
+        let path = |name: &str| {
+            std::env::temp_dir().join(format!("yahtzee-solver-{}-{}.h5", name, std::process::id()))
+        };
+
+        let complete_file = hdf5::File::create(path("complete"))?;
+        calculate_and_save_all_score_state_reward(
+            &all_dice_states,
+            &transition_function,
+            rule_set,
+            &complete_file,
+        )?;
+        assert_eq!(num_complete_layers(&complete_file, rule_set)?, num_layers);
+        check_complete(&complete_file, rule_set)?;
+
+        let resumed_file = hdf5::File::create(path("resumed"))?;
+        calculate_and_save_score_state_reward(
+            &all_dice_states,
+            &transition_function,
+            rule_set,
+            2,
+            &resumed_file,
+        )?;
+        assert_eq!(num_complete_layers(&resumed_file, rule_set)?, 2);
+        assert!(matches!(
+            check_complete(&resumed_file, rule_set),
+            Err(Error::IncompleteTables {
+                complete_layers: 2,
+                num_layers: 3
+            })
+        ));
+        // Interrupted while writing the last layer
+        resumed_file.create_group(&rule_set.layer_group_name(num_layers))?;
+        calculate_and_save_all_score_state_reward(
+            &all_dice_states,
+            &transition_function,
+            rule_set,
+            &resumed_file,
+        )?;
+        assert_eq!(num_complete_layers(&resumed_file, rule_set)?, num_layers);
+        check_complete(&resumed_file, rule_set)?;
+
+        let complete_reward = read_all_turn_start_reward(&complete_file, rule_set)?;
+        let resumed_reward = read_all_turn_start_reward(&resumed_file, rule_set)?;
+        for (complete_layer, resumed_layer) in complete_reward.iter().zip(resumed_reward.iter()) {
+            assert_eq!(complete_layer.keys, resumed_layer.keys);
+            assert_eq!(complete_layer.values, resumed_layer.values);
+        }
+        std::fs::remove_file(path("complete"))?;
+        std::fs::remove_file(path("resumed"))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,375 @@
+use crate::dice_states::NUM_DICES;
+use crate::errors::Error;
+use crate::joker_rules::JokerRule;
+use crate::rule_sets::{Game, RuleSet};
+use crate::score_states::{
+    is_four_of_a_kind, is_full_house, is_large_straight, is_small_straight, is_three_of_a_kind,
+    is_yahtzee, sum_of_dice, ScoreAction, NUM_SCORE_ACTIONS,
+};
+use ndarray::Array1;
+use serde::Deserialize;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    // Every roll, like Chance
+    Any,
+    ThreeOfAKind,
+    FourOfAKind,
+    FullHouse,
+    SmallStraight,
+    LargeStraight,
+    Yahtzee,
+}
+
+impl Pattern {
+    pub fn parse(argument: &str) -> Option<Self> {
+        match argument {
+            "any" => Some(Self::Any),
+            "three_of_a_kind" => Some(Self::ThreeOfAKind),
+            "four_of_a_kind" => Some(Self::FourOfAKind),
+            "full_house" => Some(Self::FullHouse),
+            "small_straight" => Some(Self::SmallStraight),
+            "large_straight" => Some(Self::LargeStraight),
+            "yahtzee" => Some(Self::Yahtzee),
+            _ => None,
+        }
+    }
+    pub fn is_made(&self, dice_state: &Array1<u8>) -> bool {
+        match self {
+            Self::Any => true,
+            Self::ThreeOfAKind => is_three_of_a_kind(dice_state),
+            Self::FourOfAKind => is_four_of_a_kind(dice_state),
+            Self::FullHouse => is_full_house(dice_state),
+            Self::SmallStraight => is_small_straight(dice_state),
+            Self::LargeStraight => is_large_straight(dice_state),
+            Self::Yahtzee => is_yahtzee(dice_state),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Points {
+    // The sum of every dice
+    Sum,
+    Fixed(u16),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BoxRule {
+    pub pattern: Pattern,
+    pub points: Points,
+}
+
+impl BoxRule {
+    pub fn reward(&self, dice_state: &Array1<u8>, joker: bool) -> u16 {
+        // A joker makes every pattern
+        if !joker && !self.pattern.is_made(dice_state) {
+            return 0;
+        }
+        match self.points {
+            Points::Sum => sum_of_dice(dice_state),
+            Points::Fixed(points) => points,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CustomRules {
+    // Also names the group of the tables in the output file
+    pub name: String,
+    // The boxes of the scorecard, in the order of the file
+    pub score_actions: Vec<ScoreAction>,
+    // The rule of every lower box at its ScoreAction number, the upper boxes always score the
+    // dice of their face
+    pub box_rules: [Option<BoxRule>; NUM_SCORE_ACTIONS],
+    pub joker_rule: JokerRule,
+    pub has_yahtzee_bonus: bool,
+    pub upper_score_threshold: u8,
+    // 0 when the file has no upper bonus
+    pub upper_score_bonus: u16,
+}
+
+impl CustomRules {
+    pub fn yahtzee_score(&self) -> u16 {
+        match self.box_rules[ScoreAction::Yahtzee as usize] {
+            Some(BoxRule {
+                points: Points::Fixed(points),
+                ..
+            }) => points,
+            _ => 0,
+        }
+    }
+}
+
+// The layout of a rules file, checked and turned into CustomRules by parse_custom_rules
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    name: String,
+    joker: Option<String>,
+    #[serde(default)]
+    yahtzee_bonus: bool,
+    upper_bonus: Option<UpperBonusFile>,
+    #[serde(rename = "box")]
+    boxes: Vec<BoxFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UpperBonusFile {
+    threshold: u8,
+    points: u16,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BoxFile {
+    name: String,
+    pattern: Option<String>,
+    points: Option<PointsFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PointsFile {
+    Fixed(u16),
+    Named(String),
+}
+
+pub fn load_rule_set(path: &Path) -> Result<RuleSet, Error> {
+    // The rules are read once and used until the program exits, leaking them keeps RuleSet Copy
+    let custom_rules = parse_custom_rules(&std::fs::read_to_string(path)?)?;
+    let custom_rules: &'static CustomRules = Box::leak(Box::new(custom_rules));
+    Ok(RuleSet::new(Game::Custom(custom_rules)))
+}
+
+pub fn parse_custom_rules(contents: &str) -> Result<CustomRules, Error> {
+    let rules_file: RulesFile = toml::from_str(contents)?;
+    if rules_file.name.is_empty()
+        || !rules_file
+            .name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+    {
+        return Err(Error::InvalidRulesFile(format!(
+            "name {:?} must be lowercase letters, digits and _",
+            rules_file.name
+        )));
+    }
+    if rules_file.boxes.is_empty() {
+        return Err(Error::InvalidRulesFile("no box".to_string()));
+    }
+
+    let mut score_actions = Vec::new();
+    let mut box_rules = [None; NUM_SCORE_ACTIONS];
+    for box_file in rules_file.boxes.iter() {
+        let score_action =
+            ScoreAction::parse(&box_file.name).ok_or_else(|| Error::UnknownRuleName {
+                kind: "box",
+                name: box_file.name.clone(),
+            })?;
+        if score_actions.contains(&score_action) {
+            return Err(Error::InvalidRulesFile(format!(
+                "box {} is listed twice",
+                box_file.name
+            )));
+        }
+        score_actions.push(score_action);
+        if score_action.is_upper() {
+            if box_file.pattern.is_some() || box_file.points.is_some() {
+                return Err(Error::InvalidRulesFile(format!(
+                    "upper box {} always scores the dice of its face, it takes no pattern or \
+                     points",
+                    box_file.name
+                )));
+            }
+            continue;
+        }
+        let pattern = match &box_file.pattern {
+            Some(pattern) => Pattern::parse(pattern).ok_or_else(|| Error::UnknownRuleName {
+                kind: "pattern",
+                name: pattern.clone(),
+            })?,
+            None => Pattern::Any,
+        };
+        let points = match &box_file.points {
+            Some(PointsFile::Fixed(points)) => Points::Fixed(*points),
+            Some(PointsFile::Named(points)) if points == "sum" => Points::Sum,
+            Some(PointsFile::Named(points)) => {
+                return Err(Error::UnknownRuleName {
+                    kind: "points",
+                    name: points.clone(),
+                })
+            }
+            None => {
+                return Err(Error::InvalidRulesFile(format!(
+                    "box {} needs points, a number or \"sum\"",
+                    box_file.name
+                )))
+            }
+        };
+        if score_action == ScoreAction::Yahtzee
+            && (pattern != Pattern::Yahtzee || points == Points::Sum)
+        {
+            // ScoreState records whether the Yahtzee box scored from the dice alone
+            return Err(Error::InvalidRulesFile(
+                "box yahtzee needs the yahtzee pattern and fixed points".to_string(),
+            ));
+        }
+        box_rules[score_action as usize] = Some(BoxRule { pattern, points });
+    }
+
+    let joker_rule = match &rules_file.joker {
+        Some(joker) => JokerRule::parse(joker).ok_or_else(|| Error::UnknownRuleName {
+            kind: "joker rule",
+            name: joker.clone(),
+        })?,
+        None => JokerRule::NoJoker,
+    };
+    if (joker_rule != JokerRule::NoJoker || rules_file.yahtzee_bonus)
+        && !score_actions.contains(&ScoreAction::Yahtzee)
+    {
+        return Err(Error::InvalidRulesFile(
+            "a joker rule or Yahtzee bonus needs the yahtzee box".to_string(),
+        ));
+    }
+    let (upper_score_threshold, upper_score_bonus) = match rules_file.upper_bonus {
+        Some(upper_bonus) => {
+            // The upper score is clamped at the threshold, which the upper boxes have to reach
+            let max_upper_score = score_actions
+                .iter()
+                .filter(|score_action| score_action.is_upper())
+                .map(|&score_action| NUM_DICES as u16 * (score_action as u16 + 1))
+                .sum::<u16>();
+            if upper_bonus.threshold == 0 || upper_bonus.threshold as u16 > max_upper_score {
+                return Err(Error::InvalidRulesFile(format!(
+                    "upper bonus threshold {} must be between 1 and {}, the highest upper score",
+                    upper_bonus.threshold, max_upper_score
+                )));
+            }
+            (upper_bonus.threshold, upper_bonus.points)
+        }
+        None => (0, 0),
+    };
+    Ok(CustomRules {
+        name: rules_file.name,
+        score_actions,
+        box_rules,
+        joker_rule,
+        has_yahtzee_bonus: rules_file.yahtzee_bonus,
+        upper_score_threshold,
+        upper_score_bonus,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dice_states::get_all_dice_states;
+
+    fn parse_boxes(boxes: &str) -> Result<CustomRules, Error> {
+        parse_custom_rules(&format!("name = \"test\"\n{}", boxes))
+    }
+
+    #[test]
+    fn test_yahtzee_rules_file() {
+        // The example rules file scores every roll like the built-in Yahtzee
+        let custom_rules = parse_custom_rules(include_str!("../rules/yahtzee.toml"))
+            .expect("the example rules file should be valid");
+        let rule_set = RuleSet::new(Game::Custom(Box::leak(Box::new(custom_rules))));
+        let default_rule_set = RuleSet::default();
+        assert_eq!(rule_set.score_actions(), default_rule_set.score_actions());
+        let empty_score_state = crate::score_states::ScoreState::empty();
+        let mut yahtzee_taken_state = empty_score_state;
+        yahtzee_taken_state.set_yahtzee_field(1);
+        yahtzee_taken_state.set_taken(ScoreAction::Twos);
+        for score_state in [empty_score_state, yahtzee_taken_state] {
+            for dice_state in get_all_dice_states(NUM_DICES).iter() {
+                assert_eq!(
+                    score_state.legal_score_actions(dice_state, rule_set),
+                    score_state.legal_score_actions(dice_state, default_rule_set)
+                );
+                for &score_action in rule_set.score_actions() {
+                    assert_eq!(
+                        score_state.reward(score_action, dice_state, rule_set),
+                        score_state.reward(score_action, dice_state, default_rule_set)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_unknown_names() {
+        assert!(matches!(
+            parse_boxes("[[box]]\nname = \"sevens\"\n"),
+            Err(Error::UnknownRuleName { kind: "box", .. })
+        ));
+        assert!(matches!(
+            parse_boxes("[[box]]\nname = \"chance\"\npattern = \"two_pairs\"\npoints = \"sum\"\n"),
+            Err(Error::UnknownRuleName {
+                kind: "pattern",
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse_boxes("[[box]]\nname = \"chance\"\npoints = \"double\"\n"),
+            Err(Error::UnknownRuleName { kind: "points", .. })
+        ));
+        assert!(matches!(
+            parse_boxes(
+                "joker = \"always\"\n[[box]]\nname = \"yahtzee\"\npattern = \"yahtzee\"\n\
+                 points = 50\n"
+            ),
+            Err(Error::UnknownRuleName {
+                kind: "joker rule",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_invalid_boxes() {
+        assert!(matches!(
+            parse_boxes("[[box]]\nname = \"ones\"\n[[box]]\nname = \"ones\"\n"),
+            Err(Error::InvalidRulesFile(_))
+        ));
+        assert!(matches!(
+            parse_boxes("[[box]]\nname = \"ones\"\npoints = 5\n"),
+            Err(Error::InvalidRulesFile(_))
+        ));
+        assert!(matches!(
+            parse_boxes("[[box]]\nname = \"ones\"\npoints = \"sum\"\n"),
+            Err(Error::InvalidRulesFile(_))
+        ));
+    }
+
+    #[test]
+    fn test_upper_bonus_threshold() {
+        // The upper score of ones and twos is at most 5 + 10
+        let upper_bonus = |threshold: u8| {
+            parse_boxes(&format!(
+                "[upper_bonus]\nthreshold = {}\npoints = 10\n[[box]]\nname = \"ones\"\n\
+                 [[box]]\nname = \"twos\"\n",
+                threshold
+            ))
+        };
+        assert_eq!(upper_bonus(15).unwrap().upper_score_threshold, 15);
+        assert!(matches!(upper_bonus(0), Err(Error::InvalidRulesFile(_))));
+        assert!(matches!(upper_bonus(16), Err(Error::InvalidRulesFile(_))));
+    }
+
+    #[test]
+    fn test_syntax_error() {
+        assert!(matches!(
+            parse_boxes("[[box]\nname = \"ones\"\n"),
+            Err(Error::RulesFileSyntax(_))
+        ));
+        // Unknown fields are refused rather than ignored
+        assert!(matches!(
+            parse_boxes("[[box]]\nname = \"ones\"\ncolor = \"red\"\n"),
+            Err(Error::RulesFileSyntax(_))
+        ));
+    }
+}
@@ -0,0 +1,317 @@
+use crate::dice_states::NUM_DICES;
+use crate::joker_rules::{JokerRule, YAHTZEE_BONUS};
+use crate::rule_files::CustomRules;
+use crate::score_states::{is_yahtzee, ScoreAction, ScoreState};
+use ndarray::Array1;
+
+const YAHTZEE_SCORE_ACTIONS: [ScoreAction; 13] = [
+    ScoreAction::Ones,
+    ScoreAction::Twos,
+    ScoreAction::Threes,
+    ScoreAction::Fours,
+    ScoreAction::Fives,
+    ScoreAction::Sixes,
+    ScoreAction::ThreeOfAKind,
+    ScoreAction::FourOfAKind,
+    ScoreAction::FullHouse,
+    ScoreAction::SmallStraight,
+    ScoreAction::LargeStraight,
+    ScoreAction::Chance,
+    ScoreAction::Yahtzee,
+];
+
+const YATZY_SCORE_ACTIONS: [ScoreAction; 15] = [
+    ScoreAction::Ones,
+    ScoreAction::Twos,
+    ScoreAction::Threes,
+    ScoreAction::Fours,
+    ScoreAction::Fives,
+    ScoreAction::Sixes,
+    ScoreAction::OnePair,
+    ScoreAction::TwoPairs,
+    ScoreAction::ThreeOfAKind,
+    ScoreAction::FourOfAKind,
+    ScoreAction::SmallStraight,
+    ScoreAction::LargeStraight,
+    ScoreAction::FullHouse,
+    ScoreAction::Chance,
+    ScoreAction::Yahtzee,
+];
+
+const MAXI_YATZY_SCORE_ACTIONS: [ScoreAction; 19] = [
+    ScoreAction::Ones,
+    ScoreAction::Twos,
+    ScoreAction::Threes,
+    ScoreAction::Fours,
+    ScoreAction::Fives,
+    ScoreAction::Sixes,
+    ScoreAction::OnePair,
+    ScoreAction::TwoPairs,
+    ScoreAction::ThreePairs,
+    ScoreAction::ThreeOfAKind,
+    ScoreAction::FourOfAKind,
+    ScoreAction::SmallStraight,
+    ScoreAction::LargeStraight,
+    ScoreAction::FullStraight,
+    ScoreAction::FullHouse,
+    ScoreAction::Castle,
+    ScoreAction::Tower,
+    ScoreAction::Chance,
+    ScoreAction::Yahtzee,
+];
+
+const GENERALA_SCORE_ACTIONS: [ScoreAction; 10] = [
+    ScoreAction::Ones,
+    ScoreAction::Twos,
+    ScoreAction::Threes,
+    ScoreAction::Fours,
+    ScoreAction::Fives,
+    ScoreAction::Sixes,
+    // Escalera, Full, Poker and Generala
+    ScoreAction::LargeStraight,
+    ScoreAction::FullHouse,
+    ScoreAction::FourOfAKind,
+    ScoreAction::Yahtzee,
+];
+
+const YACHT_SCORE_ACTIONS: [ScoreAction; 12] = [
+    ScoreAction::Ones,
+    ScoreAction::Twos,
+    ScoreAction::Threes,
+    ScoreAction::Fours,
+    ScoreAction::Fives,
+    ScoreAction::Sixes,
+    ScoreAction::FullHouse,
+    ScoreAction::FourOfAKind,
+    // Little and Big Straight
+    ScoreAction::SmallStraight,
+    ScoreAction::LargeStraight,
+    // Choice and Yacht
+    ScoreAction::Chance,
+    ScoreAction::Yahtzee,
+];
+
+// Generala points added to a hand made on the first roll of a turn
+pub const SERVED_BONUS: u16 = 5;
+
+// Rolls of a turn, the first roll included
+pub const DEFAULT_NUM_ROLLS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Game {
+    // American Yahtzee: 13 boxes, 35 points upper bonus, 100 points per bonus Yahtzee
+    Yahtzee(JokerRule),
+    // Scandinavian Yatzy: 15 boxes, 50 points upper bonus, sum based boxes and no joker
+    Yatzy,
+    // Maxi Yatzy: six dice, 19 boxes, 100 points Maxi Yatzy and rerolls left unused at the end
+    // of a turn are banked for the next ones. The ScoreState only has room for
+    // MAX_BANKED_REROLLS banked rerolls, the ones past it are lost.
+    MaxiYatzy,
+    // Generala: 10 boxes, no upper bonus and a bonus for the hands served on the first roll.
+    // Doble Generala is not supported.
+    Generala,
+    // Classic Yacht: 12 boxes, no upper bonus and sum based Full House and Four of a Kind
+    Yacht,
+    // Loaded from a rules file, it lives as long as the program like the built-in scorecards
+    Custom(&'static CustomRules),
+}
+
+impl Default for Game {
+    fn default() -> Self {
+        Self::Yahtzee(JokerRule::default())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RuleSet {
+    pub game: Game,
+    // Rolls of a turn, the first roll included, at least 1
+    pub num_rolls: usize,
+    // The boxes must be filled in the order of score_actions, top to bottom
+    pub forced_order: bool,
+}
+
+impl Default for RuleSet {
+    fn default() -> Self {
+        Self::new(Game::default())
+    }
+}
+
+impl RuleSet {
+    pub fn new(game: Game) -> Self {
+        Self {
+            game,
+            num_rolls: DEFAULT_NUM_ROLLS,
+            forced_order: false,
+        }
+    }
+    pub fn score_actions(&self) -> &'static [ScoreAction] {
+        // The boxes of the scorecard, in the order they are printed
+        match self.game {
+            Game::Yahtzee(_) => &YAHTZEE_SCORE_ACTIONS,
+            Game::Yatzy => &YATZY_SCORE_ACTIONS,
+            Game::MaxiYatzy => &MAXI_YATZY_SCORE_ACTIONS,
+            Game::Generala => &GENERALA_SCORE_ACTIONS,
+            Game::Yacht => &YACHT_SCORE_ACTIONS,
+            Game::Custom(custom_rules) => &custom_rules.score_actions,
+        }
+    }
+    pub fn num_dice(&self) -> usize {
+        match self.game {
+            Game::Yahtzee(_) | Game::Yatzy | Game::Generala | Game::Yacht | Game::Custom(_) => {
+                NUM_DICES
+            }
+            Game::MaxiYatzy => 6,
+        }
+    }
+    pub fn joker_rule(&self) -> JokerRule {
+        match self.game {
+            Game::Yahtzee(joker_rule) => joker_rule,
+            Game::Yatzy | Game::MaxiYatzy | Game::Generala | Game::Yacht => JokerRule::NoJoker,
+            Game::Custom(custom_rules) => custom_rules.joker_rule,
+        }
+    }
+    pub fn upper_score_threshold(&self) -> u8 {
+        // Three dice of every face
+        match self.game {
+            Game::Yahtzee(_) | Game::Yatzy | Game::Generala | Game::Yacht => 63,
+            Game::MaxiYatzy => 84,
+            Game::Custom(custom_rules) => custom_rules.upper_score_threshold,
+        }
+    }
+    pub fn upper_score_bonus(&self) -> u16 {
+        match self.game {
+            Game::Yahtzee(_) => 35,
+            Game::Yatzy | Game::MaxiYatzy => 50,
+            Game::Generala | Game::Yacht => 0,
+            Game::Custom(custom_rules) => custom_rules.upper_score_bonus,
+        }
+    }
+    pub fn has_upper_bonus(&self) -> bool {
+        // Without a bonus the upper score is not tracked, which keeps it at 0 in every
+        // ScoreState
+        self.upper_score_bonus() > 0
+    }
+    pub fn yahtzee_score(&self) -> u16 {
+        match self.game {
+            Game::Yahtzee(_) | Game::Yatzy | Game::Generala | Game::Yacht => 50,
+            Game::MaxiYatzy => 100,
+            Game::Custom(custom_rules) => custom_rules.yahtzee_score(),
+        }
+    }
+    pub fn has_yahtzee_bonus(&self) -> bool {
+        match self.game {
+            Game::Yahtzee(_) => true,
+            Game::Yatzy | Game::MaxiYatzy | Game::Generala | Game::Yacht => false,
+            Game::Custom(custom_rules) => custom_rules.has_yahtzee_bonus,
+        }
+    }
+    pub fn has_banked_rerolls(&self) -> bool {
+        match self.game {
+            Game::Yahtzee(_) | Game::Yatzy | Game::Generala | Game::Yacht | Game::Custom(_) => {
+                false
+            }
+            Game::MaxiYatzy => true,
+        }
+    }
+    pub fn has_served_bonus(&self) -> bool {
+        match self.game {
+            Game::Yahtzee(_) | Game::Yatzy | Game::MaxiYatzy | Game::Yacht | Game::Custom(_) => {
+                false
+            }
+            Game::Generala => true,
+        }
+    }
+    pub fn is_served(&self, num_rerolls: usize) -> bool {
+        // Whether dice scored with num_rerolls rerolls left are the first roll of the turn
+        self.has_served_bonus() && num_rerolls == self.num_rolls - 1
+    }
+    pub fn scores_before_last_roll(&self) -> bool {
+        // Keeping every dice and scoring after the last roll is worth less than scoring right
+        // away when the rerolls are banked or the first roll earns a bonus
+        self.has_banked_rerolls() || self.has_served_bonus()
+    }
+    pub fn bank_rerolls(&self, child_score_state: ScoreState, num_rerolls: usize) -> ScoreState {
+        // The ScoreState after scoring with num_rerolls rerolls left, which only differs from
+        // apply_action when the rerolls are banked
+        let mut child_score_state = child_score_state;
+        if self.has_banked_rerolls() {
+            child_score_state.set_banked_rerolls(num_rerolls.min(u8::MAX as usize) as u8);
+        }
+        child_score_state
+    }
+    pub fn yahtzee_bonus(&self, score_state: &ScoreState, dice_state: &Array1<u8>) -> u16 {
+        // Every extra Yahtzee scores a bonus, but only if the Yahtzee box was taken with 50
+        // points. This holds whatever the joker rule is.
+        if self.has_yahtzee_bonus() && score_state.yahtzee() == Some(true) && is_yahtzee(dice_state)
+        {
+            YAHTZEE_BONUS
+        } else {
+            0
+        }
+    }
+    pub fn legal_score_actions(
+        &self,
+        score_state: &ScoreState,
+        dice_state: &Array1<u8>,
+    ) -> Vec<ScoreAction> {
+        self.joker_rule().legal_score_actions(
+            score_state,
+            dice_state,
+            score_state.possible_score_actions(*self),
+        )
+    }
+    pub fn namespace(&self) -> String {
+        // The group holding the tables of the rule set in the output file, the default rule set
+        // stays at the root so that existing files remain readable
+        let game_namespace = match self.game {
+            Game::Yahtzee(JokerRule::ForcedUpper) => "".to_string(),
+            Game::Yahtzee(JokerRule::FreeChoice) => "yahtzee_free_choice".to_string(),
+            Game::Yahtzee(JokerRule::NoJoker) => "yahtzee_no_joker".to_string(),
+            Game::Yatzy => "yatzy".to_string(),
+            Game::MaxiYatzy => "maxi_yatzy".to_string(),
+            Game::Generala => "generala".to_string(),
+            Game::Yacht => "yacht".to_string(),
+            Game::Custom(custom_rules) => format!("rules_{}", custom_rules.name),
+        };
+        let mut suffixes = Vec::new();
+        if self.num_rolls != DEFAULT_NUM_ROLLS {
+            suffixes.push(format!("{}_rolls", self.num_rolls));
+        }
+        if self.forced_order {
+            suffixes.push("forced_order".to_string());
+        }
+        match (game_namespace.as_str(), suffixes.is_empty()) {
+            (_, true) => game_namespace,
+            ("", false) => format!("yahtzee_{}", suffixes.join("_")),
+            (_, false) => format!("{}_{}", game_namespace, suffixes.join("_")),
+        }
+    }
+    pub fn layer_group_name(&self, layer: usize) -> String {
+        match self.namespace().as_str() {
+            "" => format!("layer_{}", layer),
+            namespace => format!("{}/layer_{}", namespace, layer),
+        }
+    }
+    pub fn checkpoint_group_name(&self) -> String {
+        // The progress of the solve, next to the layers
+        match self.namespace().as_str() {
+            "" => "checkpoint".to_string(),
+            namespace => format!("{}/checkpoint", namespace),
+        }
+    }
+    pub fn parse(argument: &str) -> Option<Self> {
+        // The game of the rule set, played with the default number of rolls
+        let game = match argument {
+            "yahtzee" => Game::Yahtzee(JokerRule::ForcedUpper),
+            "yahtzee-free-choice" => Game::Yahtzee(JokerRule::FreeChoice),
+            "yahtzee-no-joker" => Game::Yahtzee(JokerRule::NoJoker),
+            "yatzy" => Game::Yatzy,
+            "maxi-yatzy" => Game::MaxiYatzy,
+            "generala" => Game::Generala,
+            "yacht" => Game::Yacht,
+            _ => return None,
+        };
+        Some(Self::new(game))
+    }
+}
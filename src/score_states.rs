@@ -1,5 +1,8 @@
-use crate::dice_states::NUM_DICES;
+use crate::dice_states::get_all_dice_states;
+use crate::rule_sets::{Game, RuleSet, SERVED_BONUS};
 use ndarray::Array1;
+use rayon::prelude::*;
+use std::collections::HashSet;
 use std::{convert::From, fmt::Display};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Ord, PartialOrd)]
@@ -18,15 +21,90 @@ pub enum ScoreAction {
     LargeStraight = 10,
     Chance = 11,
     Yahtzee = 12,
+    // Yatzy only, their bits come after the upper score
+    OnePair = 13,
+    TwoPairs = 14,
+    // Maxi Yatzy only
+    ThreePairs = 15,
+    FullStraight = 16,
+    Castle = 17,
+    Tower = 18,
 }
-const UPPER_SCORE_SHIFT: u8 = ScoreAction::Yahtzee as u8 + 4;
+// ScoreState packs the whole scorecard into a u32:
+// bits 0-11:  taken flag of Ones to Chance, one bit each at the ScoreAction number
+// bits 12-15: Yahtzee field, 0 taken with 0 points, 1-13 taken with 50 points,
+//             14 not taken, 15 is never used
+// bits 16-22: upper score, 0-105 with five dice and 0-126 with six, clamped at the upper bonus
+//             threshold since every score above it earns the same bonus
+// bits 23-28: taken flag of One Pair to Tower, one bit each at the ScoreAction number minus 13,
+//             only used by Yatzy and Maxi Yatzy
+// bits 29-31: banked rerolls, only used by Maxi Yatzy
+const YAHTZEE_SHIFT: u8 = ScoreAction::Yahtzee as u8;
+const YAHTZEE_MASK: u32 = 0b1111;
+const YAHTZEE_NOT_TAKEN: u8 = 14;
+const MAX_YAHTZEE: u8 = 13;
+const UPPER_SCORE_SHIFT: u8 = YAHTZEE_SHIFT + 4;
+const UPPER_SCORE_MASK: u32 = 0b111_1111;
+const EXTRA_TAKEN_SHIFT: u8 = UPPER_SCORE_SHIFT + 7;
+const BANKED_REROLLS_SHIFT: u8 = EXTRA_TAKEN_SHIFT + 6;
+const BANKED_REROLLS_MASK: u32 = 0b111;
+// Maxi Yatzy banks rerolls without limit, but only 3 bits are left in the ScoreState. Beyond this
+// number the extra ones are lost, which the advisor tells the player.
+pub const MAX_BANKED_REROLLS: u8 = 7;
 
-const MAX_UPPER_SCORE: u8 = 5 + 10 + 15 + 20 + 25 + 30; // 105
-const UPPER_SCORE_THRESHOLD: u8 = 63;
-const UPPER_SCORE_BONUS: u8 = 35;
+pub const NUM_SCORE_ACTIONS: usize = 19;
+const MAX_UPPER_SCORE: u8 = 6 + 12 + 18 + 24 + 30 + 36; // 126
+
+const UPPER_SCORE_ACTIONS: [ScoreAction; 6] = [
+    ScoreAction::Ones,
+    ScoreAction::Twos,
+    ScoreAction::Threes,
+    ScoreAction::Fours,
+    ScoreAction::Fives,
+    ScoreAction::Sixes,
+];
 
 impl ScoreAction {
-    pub fn all() -> [Self; 13] {
+    pub fn is_upper(&self) -> bool {
+        UPPER_SCORE_ACTIONS.contains(self)
+    }
+    fn taken_shift(&self) -> u8 {
+        match self {
+            Self::OnePair
+            | Self::TwoPairs
+            | Self::ThreePairs
+            | Self::FullStraight
+            | Self::Castle
+            | Self::Tower => EXTRA_TAKEN_SHIFT + *self as u8 - Self::OnePair as u8,
+            _ => *self as u8,
+        }
+    }
+    pub fn parse(argument: &str) -> Option<Self> {
+        // The snake case name of the box
+        match argument {
+            "ones" => Some(Self::Ones),
+            "twos" => Some(Self::Twos),
+            "threes" => Some(Self::Threes),
+            "fours" => Some(Self::Fours),
+            "fives" => Some(Self::Fives),
+            "sixes" => Some(Self::Sixes),
+            "three_of_a_kind" => Some(Self::ThreeOfAKind),
+            "four_of_a_kind" => Some(Self::FourOfAKind),
+            "full_house" => Some(Self::FullHouse),
+            "small_straight" => Some(Self::SmallStraight),
+            "large_straight" => Some(Self::LargeStraight),
+            "chance" => Some(Self::Chance),
+            "yahtzee" => Some(Self::Yahtzee),
+            "one_pair" => Some(Self::OnePair),
+            "two_pairs" => Some(Self::TwoPairs),
+            "three_pairs" => Some(Self::ThreePairs),
+            "full_straight" => Some(Self::FullStraight),
+            "castle" => Some(Self::Castle),
+            "tower" => Some(Self::Tower),
+            _ => None,
+        }
+    }
+    pub fn all() -> [Self; NUM_SCORE_ACTIONS] {
         [
             Self::Ones,
             Self::Twos,
@@ -41,6 +119,12 @@ impl ScoreAction {
             Self::LargeStraight,
             Self::Chance,
             Self::Yahtzee,
+            Self::OnePair,
+            Self::TwoPairs,
+            Self::ThreePairs,
+            Self::FullStraight,
+            Self::Castle,
+            Self::Tower,
         ]
     }
 }
@@ -51,9 +135,10 @@ pub struct ScoreState {
 }
 
 impl ScoreState {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        upper_score: u8,       // 0-105: 7 bits
-        yahtzee: Option<u8>,   // 0-13 taken yahtzee reward, 14 not taken, 15 not applicable: 4 bits
+        upper_score: u8,       // 0-126: 7 bits
+        yahtzee: Option<u8>,   // 0-13 taken (0 scored nothing), None not taken: 4 bits
         ones: bool,            // 0-1: 1 bit
         twos: bool,            // 0-1: 1 bit
         threes: bool,          // 0-1: 1 bit
@@ -67,31 +152,28 @@ impl ScoreState {
         large_straight: bool,  // 0-1: 1 bit
         chance: bool,          // 0-1: 1 bit
     ) -> Self {
-        let mut state: u32 = 0;
-        state |= (ones as u32) << ScoreAction::Ones as u8;
-        state |= (twos as u32) << ScoreAction::Twos as u8;
-        state |= (threes as u32) << ScoreAction::Threes as u8;
-        state |= (fours as u32) << ScoreAction::Fours as u8;
-        state |= (fives as u32) << ScoreAction::Fives as u8;
-        state |= (sixes as u32) << ScoreAction::Sixes as u8;
-        state |= (three_of_a_kind as u32) << ScoreAction::ThreeOfAKind as u8;
-        state |= (four_of_a_kind as u32) << ScoreAction::FourOfAKind as u8;
-        state |= (full_house as u32) << ScoreAction::FullHouse as u8;
-        state |= (small_straight as u32) << ScoreAction::SmallStraight as u8;
-        state |= (large_straight as u32) << ScoreAction::LargeStraight as u8;
-        state |= (chance as u32) << ScoreAction::Chance as u8;
-        let yahtzee = yahtzee.unwrap_or(14);
-        if yahtzee > 14 {
-            panic!("Yahtzee count must be 0-13, 14 is also not taken");
-        } else {
-            state |= (yahtzee as u32) << ScoreAction::Yahtzee as u8;
-        }
-        if upper_score > MAX_UPPER_SCORE {
-            panic!("Upper sum must be 0-105");
-        } else {
-            state |= (upper_score as u32) << UPPER_SCORE_SHIFT
+        let mut score_state = Self { state: 0 };
+        for (score_action, taken) in [
+            (ScoreAction::Ones, ones),
+            (ScoreAction::Twos, twos),
+            (ScoreAction::Threes, threes),
+            (ScoreAction::Fours, fours),
+            (ScoreAction::Fives, fives),
+            (ScoreAction::Sixes, sixes),
+            (ScoreAction::ThreeOfAKind, three_of_a_kind),
+            (ScoreAction::FourOfAKind, four_of_a_kind),
+            (ScoreAction::FullHouse, full_house),
+            (ScoreAction::SmallStraight, small_straight),
+            (ScoreAction::LargeStraight, large_straight),
+            (ScoreAction::Chance, chance),
+        ] {
+            if taken {
+                score_state.set_taken(score_action);
+            }
         }
-        Self { state }
+        score_state.set_yahtzee_field(yahtzee.unwrap_or(YAHTZEE_NOT_TAKEN));
+        score_state.set_upper_score(upper_score);
+        score_state
     }
     pub fn empty() -> Self {
         Self::new(
@@ -99,427 +181,726 @@ impl ScoreState {
             false,
         )
     }
+    pub fn ones(&self) -> bool {
+        self.is_taken(ScoreAction::Ones)
+    }
+    pub fn twos(&self) -> bool {
+        self.is_taken(ScoreAction::Twos)
+    }
+    pub fn threes(&self) -> bool {
+        self.is_taken(ScoreAction::Threes)
+    }
+    pub fn fours(&self) -> bool {
+        self.is_taken(ScoreAction::Fours)
+    }
+    pub fn fives(&self) -> bool {
+        self.is_taken(ScoreAction::Fives)
+    }
+    pub fn sixes(&self) -> bool {
+        self.is_taken(ScoreAction::Sixes)
+    }
+    pub fn three_of_a_kind(&self) -> bool {
+        self.is_taken(ScoreAction::ThreeOfAKind)
+    }
+    pub fn four_of_a_kind(&self) -> bool {
+        self.is_taken(ScoreAction::FourOfAKind)
+    }
+    pub fn full_house(&self) -> bool {
+        self.is_taken(ScoreAction::FullHouse)
+    }
+    pub fn small_straight(&self) -> bool {
+        self.is_taken(ScoreAction::SmallStraight)
+    }
+    pub fn large_straight(&self) -> bool {
+        self.is_taken(ScoreAction::LargeStraight)
+    }
+    pub fn chance(&self) -> bool {
+        self.is_taken(ScoreAction::Chance)
+    }
+    pub fn one_pair(&self) -> bool {
+        self.is_taken(ScoreAction::OnePair)
+    }
+    pub fn two_pairs(&self) -> bool {
+        self.is_taken(ScoreAction::TwoPairs)
+    }
+    pub fn three_pairs(&self) -> bool {
+        self.is_taken(ScoreAction::ThreePairs)
+    }
+    pub fn full_straight(&self) -> bool {
+        self.is_taken(ScoreAction::FullStraight)
+    }
+    pub fn castle(&self) -> bool {
+        self.is_taken(ScoreAction::Castle)
+    }
+    pub fn tower(&self) -> bool {
+        self.is_taken(ScoreAction::Tower)
+    }
+    pub fn banked_rerolls(&self) -> u8 {
+        ((self.state >> BANKED_REROLLS_SHIFT) & BANKED_REROLLS_MASK) as u8
+    }
+    pub fn set_banked_rerolls(&mut self, banked_rerolls: u8) {
+        // Rerolls past MAX_BANKED_REROLLS are lost
+        let banked_rerolls = banked_rerolls.min(MAX_BANKED_REROLLS);
+        self.state &= !(BANKED_REROLLS_MASK << BANKED_REROLLS_SHIFT);
+        self.state |= (banked_rerolls as u32) << BANKED_REROLLS_SHIFT;
+    }
+    pub fn yahtzee(&self) -> Option<bool> {
+        // None if not taken, Some(true) if taken with 50 points, Some(false) if taken with 0
+        match self.yahtzee_field() {
+            0 => Some(false),
+            1..=MAX_YAHTZEE => Some(true),
+            _ => None,
+        }
+    }
+    pub fn yahtzee_field(&self) -> u8 {
+        ((self.state >> YAHTZEE_SHIFT) & YAHTZEE_MASK) as u8
+    }
+    pub fn set_yahtzee_field(&mut self, yahtzee: u8) {
+        if yahtzee > YAHTZEE_NOT_TAKEN {
+            panic!("Yahtzee count must be 0-13, 14 is also not taken");
+        }
+        self.state &= !(YAHTZEE_MASK << YAHTZEE_SHIFT);
+        self.state |= (yahtzee as u32) << YAHTZEE_SHIFT;
+    }
     pub fn is_taken(&self, score_action: ScoreAction) -> bool {
         if score_action != ScoreAction::Yahtzee {
-            self.state & (0b1 << score_action as u8) != 0
+            self.state & (0b1 << score_action.taken_shift()) != 0
         } else {
-            self.state & (0b1111 << score_action as u8) < 14
+            self.yahtzee().is_some()
         }
     }
     pub fn set_taken(&mut self, score_action: ScoreAction) {
+        // Taking Yahtzee this way scores 0, apply_action sets the field for a real Yahtzee
         if score_action != ScoreAction::Yahtzee {
-            self.state |= 0b1 << score_action as u8;
+            self.state |= 0b1 << score_action.taken_shift();
         } else {
-            self.state |= 0b1111 << 14;
+            self.set_yahtzee_field(0);
+        }
+    }
+    pub fn set_not_taken(&mut self, score_action: ScoreAction) {
+        if score_action != ScoreAction::Yahtzee {
+            self.state &= !(0b1 << score_action.taken_shift());
+        } else {
+            self.set_yahtzee_field(YAHTZEE_NOT_TAKEN);
         }
     }
     pub fn upper_score(&self) -> u8 {
-        ((self.state & (0b1111111 << UPPER_SCORE_SHIFT)) >> UPPER_SCORE_SHIFT) as u8
+        ((self.state >> UPPER_SCORE_SHIFT) & UPPER_SCORE_MASK) as u8
     }
     pub fn set_upper_score(&mut self, upper_score: u8) {
         if upper_score > MAX_UPPER_SCORE {
-            panic!("Upper sum must be 0-105");
+            panic!("Upper sum must be 0-126");
         }
+        self.state &= !(UPPER_SCORE_MASK << UPPER_SCORE_SHIFT);
         self.state |= (upper_score as u32) << UPPER_SCORE_SHIFT;
     }
-    pub fn apply_action(&self, score_action: ScoreAction, dice_state: &Array1<u8>) -> Option<Self> {
+    pub fn is_valid(&self, rule_set: RuleSet) -> bool {
+        // Whether the state is a scorecard of the rule set whose upper score and Yahtzee count
+        // can be reached with the boxes taken so far
+        ScoreAction::all().iter().all(|score_action| {
+            !self.is_taken(*score_action) || rule_set.score_actions().contains(score_action)
+        }) && self.yahtzee_field() <= YAHTZEE_NOT_TAKEN
+            && (self.yahtzee_field() == YAHTZEE_NOT_TAKEN
+                || self.yahtzee_field() as usize <= self.num_taken())
+            && self.upper_score() <= self.max_upper_score(rule_set)
+            && self.upper_score() <= rule_set.upper_score_threshold()
+            && (rule_set.has_banked_rerolls() || self.banked_rerolls() == 0)
+    }
+    fn max_upper_score(&self, rule_set: RuleSet) -> u8 {
+        UPPER_SCORE_ACTIONS
+            .iter()
+            .filter(|&&score_action| self.is_taken(score_action))
+            .map(|&score_action| rule_set.num_dice() as u8 * (score_action as u8 + 1))
+            .sum()
+    }
+    pub fn apply_action(
+        &self,
+        score_action: ScoreAction,
+        dice_state: &Array1<u8>,
+        rule_set: RuleSet,
+    ) -> Option<Self> {
         let mut new_state = Self { state: self.state };
         if self.is_taken(score_action) {
             return None;
         }
         new_state.set_taken(score_action);
+        // The rerolls left after scoring are banked by the solver, see bank_rerolls
+        new_state.set_banked_rerolls(0);
+        if rule_set.yahtzee_bonus(self, dice_state) > 0 {
+            // Count the bonus Yahtzee, the Yahtzee box itself is already taken
+            new_state.set_yahtzee_field((self.yahtzee_field() + 1).min(MAX_YAHTZEE));
+        }
         match score_action {
             ScoreAction::Ones
             | ScoreAction::Twos
             | ScoreAction::Threes
             | ScoreAction::Fours
             | ScoreAction::Fives
-            | ScoreAction::Sixes => {
+            | ScoreAction::Sixes
+                if rule_set.has_upper_bonus() =>
+            {
                 let score = self.upper_score()
-                    + (dice_state[score_action as usize] as u8) * (score_action as u8 + 1);
-                new_state.set_upper_score(score);
+                    + dice_state[score_action as usize] * (score_action as u8 + 1);
+                new_state.set_upper_score(score.min(rule_set.upper_score_threshold()));
+            }
+            ScoreAction::Yahtzee => {
+                new_state.set_yahtzee_field(is_yahtzee(dice_state) as u8);
             }
             _ => (),
         }
         Some(new_state)
     }
-    pub fn score(&self) -> u16 {
-        let mut score = self.upper_score() as u16;
-        if score >= UPPER_SCORE_THRESHOLD as u16 {
-            score += UPPER_SCORE_BONUS as u16;
-        }
-
-        score
+    pub fn num_taken(&self) -> usize {
+        ScoreAction::all()
+            .iter()
+            .filter(|&&score_action| self.is_taken(score_action))
+            .count()
     }
-    pub fn possible_score_actions(&self) -> Vec<ScoreAction> {
-        let mut possible_score_actions = Vec::new();
-        if self.ones().is_none() {
-            possible_score_actions.push(ScoreAction::Ones);
-        }
-        if self.twos().is_none() {
-            possible_score_actions.push(ScoreAction::Twos);
-        }
-        if self.threes().is_none() {
-            possible_score_actions.push(ScoreAction::Threes);
-        }
-        if self.fours().is_none() {
-            possible_score_actions.push(ScoreAction::Fours);
-        }
-        if self.fives().is_none() {
-            possible_score_actions.push(ScoreAction::Fives);
-        }
-        if self.sixes().is_none() {
-            possible_score_actions.push(ScoreAction::Sixes);
-        }
-        if !self.three_of_a_kind() {
-            possible_score_actions.push(ScoreAction::ThreeOfAKind);
-        }
-        if !self.four_of_a_kind() {
-            possible_score_actions.push(ScoreAction::FourOfAKind);
-        }
-        if !self.full_house() {
-            possible_score_actions.push(ScoreAction::FullHouse);
+    pub fn upper_bonus(&self, rule_set: RuleSet) -> u16 {
+        if self.upper_score() >= rule_set.upper_score_threshold() {
+            rule_set.upper_score_bonus()
+        } else {
+            0
         }
-        if !self.small_straight() {
-            possible_score_actions.push(ScoreAction::SmallStraight);
+    }
+    pub fn yahtzee_bonus_count(&self) -> u16 {
+        // The Yahtzee field counts the 50 points Yahtzee and every bonus Yahtzee after it
+        match self.yahtzee_field() {
+            1..=MAX_YAHTZEE => self.yahtzee_field() as u16 - 1,
+            _ => 0,
         }
-        if !self.large_straight() {
-            possible_score_actions.push(ScoreAction::LargeStraight);
+    }
+    pub fn terminal_reward(&self, rule_set: RuleSet) -> u16 {
+        // The part of the final score that no reward collects during the game, which only
+        // depends on the completed scorecard
+        self.upper_bonus(rule_set)
+    }
+    pub fn possible_score_actions(&self, rule_set: RuleSet) -> Vec<ScoreAction> {
+        let possible_score_actions = rule_set
+            .score_actions()
+            .iter()
+            .copied()
+            .filter(|&score_action| !self.is_taken(score_action));
+        if rule_set.forced_order {
+            // Only the first open box, top to bottom
+            possible_score_actions.take(1).collect()
+        } else {
+            possible_score_actions.collect()
         }
-        if !self.chance() {
-            possible_score_actions.push(ScoreAction::Chance);
+    }
+    pub fn get_parent_states(&self, rule_set: RuleSet) -> Vec<Self> {
+        // All valid states that reach this state with one apply_action
+        let mut parent_states = Vec::new();
+        for &score_action in rule_set.score_actions() {
+            if !self.is_taken(score_action) {
+                continue;
+            }
+            let mut parent_state = *self;
+            parent_state.set_not_taken(score_action);
+            if rule_set.forced_order
+                && parent_state.possible_score_actions(rule_set) != [score_action]
+            {
+                // Only the last box filled in order can be the one just taken
+                continue;
+            }
+            parent_states.extend(parent_state.get_parents_through(
+                score_action,
+                self,
+                false,
+                rule_set,
+            ));
+            if score_action != ScoreAction::Yahtzee
+                && (2..=MAX_YAHTZEE).contains(&self.yahtzee_field())
+            {
+                // The box could also have been taken with a bonus Yahtzee
+                parent_state.set_yahtzee_field(self.yahtzee_field() - 1);
+                parent_states.extend(parent_state.get_parents_through(
+                    score_action,
+                    self,
+                    true,
+                    rule_set,
+                ));
+            }
         }
-        if self.yahtzee().is_none() {
-            possible_score_actions.push(ScoreAction::Yahtzee);
+        if rule_set.has_banked_rerolls() {
+            // The rerolls banked are the ones left when scoring, so the parent started its turn
+            // with at least as many as the turn's own rerolls could not provide
+            let min_banked_rerolls = self
+                .banked_rerolls()
+                .saturating_sub(rule_set.num_rolls as u8 - 1);
+            parent_states = parent_states
+                .into_iter()
+                .flat_map(|parent_state| {
+                    (min_banked_rerolls..=MAX_BANKED_REROLLS).map(move |banked_rerolls| {
+                        let mut parent_state = parent_state;
+                        parent_state.set_banked_rerolls(banked_rerolls);
+                        parent_state
+                    })
+                })
+                .collect();
         }
-        possible_score_actions
+        parent_states
     }
-    pub fn get_parent_states(&self) -> Vec<Self> {
+    fn get_parents_through(
+        &self,
+        score_action: ScoreAction,
+        child_state: &Self,
+        bonus: bool,
+        rule_set: RuleSet,
+    ) -> Vec<Self> {
+        // All valid variations of self, a parent of child_state with score_action not taken,
+        // that differ only in how many points score_action added to the upper score
         let mut parent_states = Vec::new();
-        if let Some(_) = self.ones() {
-            parent_states.push(Self {
-                state: self.state | (0b111 << 23),
-            });
-        }
-        if let Some(_) = self.twos() {
-            parent_states.push(Self {
-                state: self.state | (0b111 << 20),
-            });
-        }
-        if let Some(_) = self.threes() {
-            parent_states.push(Self {
-                state: self.state | (0b111 << 17),
-            });
-        }
-        if let Some(_) = self.fours() {
-            parent_states.push(Self {
-                state: self.state | (0b111 << 14),
-            });
-        }
-        if let Some(_) = self.fives() {
-            parent_states.push(Self {
-                state: self.state | (0b111 << 11),
-            });
-        }
-        if let Some(_) = self.sixes() {
-            parent_states.push(Self {
-                state: self.state | (0b111 << 8),
-            });
-        }
-        if self.three_of_a_kind() {
-            parent_states.push(Self {
-                state: self.state & !(0b1 << 7),
-            });
-        }
-        if self.four_of_a_kind() {
-            parent_states.push(Self {
-                state: self.state & !(0b1 << 6),
-            });
-        }
-        if self.full_house() {
-            parent_states.push(Self {
-                state: self.state & !(0b1 << 5),
-            });
-        }
-        if self.small_straight() {
-            parent_states.push(Self {
-                state: self.state & !(0b1 << 4),
-            });
-        }
-        if self.large_straight() {
-            parent_states.push(Self {
-                state: self.state & !(0b1 << 3),
-            });
-        }
-        if self.chance() {
-            parent_states.push(Self {
-                state: self.state & !(0b1 << 2),
-            });
-        }
-        if let Some(yahtzee) = self.yahtzee() {
-            if yahtzee {
-                parent_states.push(Self {
-                    state: (self.state & !(0b11)) | 0b10,
-                });
+        let mut parent_state = *self;
+        match score_action {
+            ScoreAction::Ones
+            | ScoreAction::Twos
+            | ScoreAction::Threes
+            | ScoreAction::Fours
+            | ScoreAction::Fives
+            | ScoreAction::Sixes => {
+                // The upper box could have been taken with any number of its dice, as long as
+                // rolling a Yahtzee matches whether a bonus was counted
+                let face = score_action as u8 + 1;
+                let num_dice = rule_set.num_dice() as u8;
+                let all_num_dice = if !rule_set.has_upper_bonus() {
+                    // The upper score is not tracked
+                    vec![0]
+                } else if bonus {
+                    vec![0, num_dice]
+                } else if rule_set.has_yahtzee_bonus() && self.yahtzee() == Some(true) {
+                    (0..num_dice).collect()
+                } else {
+                    (0..=num_dice).collect()
+                };
+                let threshold = rule_set.upper_score_threshold();
+                for num_dice in all_num_dice {
+                    if rule_set.has_upper_bonus() && child_state.upper_score() == threshold {
+                        // The child was clamped, so the parent had any score that reaches the
+                        // threshold with the dice of this box
+                        for upper_score in threshold.saturating_sub(num_dice * face)..=threshold {
+                            parent_state.set_upper_score(upper_score);
+                            parent_states.push(parent_state);
+                        }
+                        continue;
+                    }
+                    if num_dice * face > child_state.upper_score() {
+                        break;
+                    }
+                    parent_state.set_upper_score(child_state.upper_score() - num_dice * face);
+                    parent_states.push(parent_state);
+                }
+                parent_states.sort_by_key(|&parent_state| u32::from(parent_state));
+                parent_states.dedup();
             }
+            ScoreAction::Yahtzee => {
+                // Only a freshly taken Yahtzee box has a parent through the Yahtzee action
+                if child_state.yahtzee_field() <= 1 {
+                    parent_states.push(parent_state);
+                }
+            }
+            _ => parent_states.push(parent_state),
         }
+        parent_states.retain(|parent_state| parent_state.is_valid(rule_set));
         parent_states
     }
-    pub fn get_all_terminal_states() -> Vec<ScoreState> {
-        let mut terminal_states = Vec::new();
-        for ones in 0..=NUM_DICES {
-            for twos in 0..=NUM_DICES {
-                for threes in 0..=NUM_DICES {
-                    for fours in 0..=NUM_DICES {
-                        for fives in 0..=NUM_DICES {
-                            for sixes in 0..=NUM_DICES {
-                                for yahtzee in 0..=13 {
-                                    terminal_states.push(Self::new(
-                                        (ones
-                                            + twos * 2
-                                            + threes * 3
-                                            + fours * 4
-                                            + fives * 5
-                                            + sixes * 6)
-                                            as u8,
-                                        Some(yahtzee),
-                                        false,
-                                        false,
-                                        false,
-                                        false,
-                                        false,
-                                        false,
-                                        false,
-                                        false,
-                                        false,
-                                        false,
-                                        false,
-                                        false,
-                                    ));
-                                }
+    pub fn get_all_reachable_states(rule_set: RuleSet) -> Vec<Vec<ScoreState>> {
+        // Every state reachable from the empty scorecard, by number of boxes taken. Unlike the
+        // terminal states and their parents, this skips the upper scores and Yahtzee counts that
+        // no game can reach.
+        Self::get_reachable_states(rule_set, rule_set.score_actions().len())
+    }
+    pub fn get_reachable_states(rule_set: RuleSet, max_num_taken: usize) -> Vec<Vec<ScoreState>> {
+        // The reachable states with up to max_num_taken boxes taken, by number of boxes taken
+        let all_dice_states = get_all_dice_states(rule_set.num_dice());
+        // Apart from Yahtzees, which the joker and bonus rules tell apart, apply_action only
+        // depends on the dice of the box's face. One dice state per outcome is enough.
+        let distinct_dice_states = ScoreAction::all().map(|score_action| {
+            let mut outcomes = HashSet::new();
+            all_dice_states
+                .iter()
+                .filter(|dice_state| {
+                    let outcome = if is_yahtzee(dice_state) {
+                        dice_state.to_vec()
+                    } else if score_action.is_upper() {
+                        vec![u8::MAX, dice_state[score_action as usize]]
+                    } else {
+                        vec![u8::MAX]
+                    };
+                    outcomes.insert(outcome)
+                })
+                .collect::<Vec<_>>()
+        });
+
+        let mut layers = vec![vec![Self::empty()]];
+        for _ in 0..max_num_taken {
+            // Every worker lists the children of its own states, duplicates are removed once
+            // sorted
+            let mut next_layer = layers[layers.len() - 1]
+                .par_iter()
+                .flat_map_iter(|score_state| {
+                    score_state.get_child_states(&distinct_dice_states, rule_set)
+                })
+                .map(u32::from)
+                .collect::<Vec<_>>();
+            next_layer.par_sort_unstable();
+            next_layer.dedup();
+            layers.push(next_layer.into_iter().map(Self::from).collect());
+        }
+        layers
+    }
+    fn get_child_states(
+        &self,
+        distinct_dice_states: &[Vec<&Array1<u8>>],
+        rule_set: RuleSet,
+    ) -> Vec<Self> {
+        // The states apply_action reaches from this state, possibly more than once
+        let max_rerolls = if rule_set.has_banked_rerolls() {
+            rule_set.num_rolls - 1 + self.banked_rerolls() as usize
+        } else {
+            0
+        };
+        let mut child_states = Vec::new();
+        for score_action in self.possible_score_actions(rule_set) {
+            for dice_state in distinct_dice_states[score_action as usize].iter() {
+                if !self
+                    .legal_score_actions(dice_state, rule_set)
+                    .contains(&score_action)
+                {
+                    continue;
+                }
+                let child_state = self
+                    .apply_action(score_action, dice_state, rule_set)
+                    .expect("possible_score_actions should only return valid actions");
+                child_states.extend(
+                    (0..=max_rerolls)
+                        .map(|num_rerolls| rule_set.bank_rerolls(child_state, num_rerolls)),
+                );
+            }
+        }
+        child_states
+    }
+    pub fn get_all_terminal_states(rule_set: RuleSet) -> Vec<ScoreState> {
+        let num_dice = rule_set.num_dice();
+        let mut upper_scores = Vec::new();
+        for ones in 0..=num_dice {
+            for twos in 0..=num_dice {
+                for threes in 0..=num_dice {
+                    for fours in 0..=num_dice {
+                        for fives in 0..=num_dice {
+                            for sixes in 0..=num_dice {
+                                upper_scores.push(
+                                    ((ones
+                                        + twos * 2
+                                        + threes * 3
+                                        + fours * 4
+                                        + fives * 5
+                                        + sixes * 6) as u8)
+                                        .min(rule_set.upper_score_threshold()),
+                                );
                             }
                         }
                     }
                 }
             }
         }
+        upper_scores.sort();
+        upper_scores.dedup();
+        if !rule_set.has_upper_bonus() {
+            // The upper score is not tracked
+            upper_scores = vec![0];
+        }
+
+        // Without bonus Yahtzees the field is only 0 or 1, and without a Yahtzee box it is never
+        // taken
+        let yahtzee_fields = if !rule_set.score_actions().contains(&ScoreAction::Yahtzee) {
+            YAHTZEE_NOT_TAKEN..=YAHTZEE_NOT_TAKEN
+        } else if rule_set.has_yahtzee_bonus() {
+            0..=MAX_YAHTZEE
+        } else {
+            0..=1
+        };
+        // Rerolls banked on the last turn are left unused
+        let max_banked_rerolls = if rule_set.has_banked_rerolls() {
+            MAX_BANKED_REROLLS
+        } else {
+            0
+        };
+        let mut all_taken_state = Self::empty();
+        for &score_action in rule_set.score_actions() {
+            all_taken_state.set_taken(score_action);
+        }
+        let mut terminal_states = Vec::new();
+        for upper_score in upper_scores {
+            for yahtzee in yahtzee_fields.clone() {
+                for banked_rerolls in 0..=max_banked_rerolls {
+                    let mut terminal_state = all_taken_state;
+                    terminal_state.set_upper_score(upper_score);
+                    terminal_state.set_yahtzee_field(yahtzee);
+                    terminal_state.set_banked_rerolls(banked_rerolls);
+                    terminal_states.push(terminal_state);
+                }
+            }
+        }
         terminal_states
     }
-    fn three_of_a_kind_reward(&self, dice_state: &Array1<u8>) -> u16 {
-        if !self.three_of_a_kind() {
+    pub fn legal_score_actions(
+        &self,
+        dice_state: &Array1<u8>,
+        rule_set: RuleSet,
+    ) -> Vec<ScoreAction> {
+        rule_set.legal_score_actions(self, dice_state)
+    }
+    pub fn reward(
+        &self,
+        score_action: ScoreAction,
+        dice_state: &Array1<u8>,
+        rule_set: RuleSet,
+    ) -> u16 {
+        // The reward of scoring the dice of the last roll
+        self.reward_on_roll(score_action, dice_state, 0, rule_set)
+    }
+    pub fn reward_on_roll(
+        &self,
+        score_action: ScoreAction,
+        dice_state: &Array1<u8>,
+        num_rerolls: usize,
+        rule_set: RuleSet,
+    ) -> u16 {
+        // The reward of scoring the dice with num_rerolls rerolls left, which only differs from
+        // the last roll when the first roll earns a served bonus
+        if self.is_taken(score_action) {
             return 0;
         }
-        if let Some(yahtzee) = self.yahtzee() {
-            if is_yahtzee(dice_state) {
-                let reward: u16 = dice_state.iter().map(|&x| x as u16).sum();
-                if yahtzee {
-                    // If the dice state is yahtzee and
-                    // the score state has already taken yahtzee
-                    // with a non-zero reward, then we add 100
-                    return reward + 100;
+        let reward = match rule_set.game {
+            Game::Yahtzee(joker_rule) => {
+                let joker = joker_rule.is_joker(self, dice_state);
+                match score_action {
+                    ScoreAction::Ones
+                    | ScoreAction::Twos
+                    | ScoreAction::Threes
+                    | ScoreAction::Fours
+                    | ScoreAction::Fives
+                    | ScoreAction::Sixes => self.upper_reward(score_action, dice_state),
+                    ScoreAction::ThreeOfAKind => self.three_of_a_kind_reward(dice_state),
+                    ScoreAction::FourOfAKind => self.four_of_a_kind_reward(dice_state),
+                    ScoreAction::FullHouse => self.full_house_reward(dice_state, joker),
+                    ScoreAction::SmallStraight => self.small_straight_reward(dice_state, joker),
+                    ScoreAction::LargeStraight => self.large_straight_reward(dice_state, joker),
+                    ScoreAction::Chance => self.chance_reward(dice_state),
+                    ScoreAction::Yahtzee => self.yahtzee_reward(dice_state, rule_set),
+                    _ => 0,
+                }
+            }
+            Game::Yatzy | Game::MaxiYatzy => match score_action {
+                ScoreAction::Ones
+                | ScoreAction::Twos
+                | ScoreAction::Threes
+                | ScoreAction::Fours
+                | ScoreAction::Fives
+                | ScoreAction::Sixes => self.upper_reward(score_action, dice_state),
+                ScoreAction::OnePair => self.n_of_a_kind_reward(dice_state, 2),
+                ScoreAction::TwoPairs => self.n_pairs_reward(dice_state, 2),
+                ScoreAction::ThreePairs => self.n_pairs_reward(dice_state, 3),
+                ScoreAction::ThreeOfAKind => self.n_of_a_kind_reward(dice_state, 3),
+                ScoreAction::FourOfAKind => self.n_of_a_kind_reward(dice_state, 4),
+                ScoreAction::SmallStraight => self.yatzy_straight_reward(dice_state, 0, 5),
+                ScoreAction::LargeStraight => self.yatzy_straight_reward(dice_state, 1, 5),
+                ScoreAction::FullStraight => self.yatzy_straight_reward(dice_state, 0, 6),
+                ScoreAction::FullHouse => self.house_reward(dice_state, 3, 2),
+                ScoreAction::Castle => self.house_reward(dice_state, 3, 3),
+                ScoreAction::Tower => self.house_reward(dice_state, 4, 2),
+                ScoreAction::Chance => self.chance_reward(dice_state),
+                ScoreAction::Yahtzee => self.yahtzee_reward(dice_state, rule_set),
+            },
+            Game::Generala => {
+                let served = rule_set.is_served(num_rerolls);
+                match score_action {
+                    ScoreAction::Ones
+                    | ScoreAction::Twos
+                    | ScoreAction::Threes
+                    | ScoreAction::Fours
+                    | ScoreAction::Fives
+                    | ScoreAction::Sixes => self.upper_reward(score_action, dice_state),
+                    ScoreAction::LargeStraight => {
+                        self.generala_hand_reward(is_large_straight(dice_state), 20, served)
+                    }
+                    ScoreAction::FullHouse => {
+                        self.generala_hand_reward(is_full_house(dice_state), 30, served)
+                    }
+                    ScoreAction::FourOfAKind => {
+                        self.generala_hand_reward(is_four_of_a_kind(dice_state), 40, served)
+                    }
+                    // A served Generala wins the game outright, which no number of points
+                    // expresses, so it scores like any other Generala
+                    ScoreAction::Yahtzee => self.yahtzee_reward(dice_state, rule_set),
+                    _ => 0,
+                }
+            }
+            Game::Yacht => match score_action {
+                ScoreAction::Ones
+                | ScoreAction::Twos
+                | ScoreAction::Threes
+                | ScoreAction::Fours
+                | ScoreAction::Fives
+                | ScoreAction::Sixes => self.upper_reward(score_action, dice_state),
+                ScoreAction::FullHouse => self.house_reward(dice_state, 3, 2),
+                ScoreAction::FourOfAKind => self.n_of_a_kind_reward(dice_state, 4),
+                ScoreAction::SmallStraight => self.yacht_straight_reward(dice_state, 0),
+                ScoreAction::LargeStraight => self.yacht_straight_reward(dice_state, 1),
+                ScoreAction::Chance => self.chance_reward(dice_state),
+                ScoreAction::Yahtzee => self.yahtzee_reward(dice_state, rule_set),
+                _ => 0,
+            },
+            Game::Custom(custom_rules) => {
+                if score_action.is_upper() {
+                    self.upper_reward(score_action, dice_state)
                 } else {
-                    return reward;
+                    let joker = rule_set.joker_rule().is_joker(self, dice_state);
+                    custom_rules.box_rules[score_action as usize]
+                        .map_or(0, |box_rule| box_rule.reward(dice_state, joker))
                 }
             }
-        }
+        };
+        reward + rule_set.yahtzee_bonus(self, dice_state)
+    }
+    fn upper_reward(&self, score_action: ScoreAction, dice_state: &Array1<u8>) -> u16 {
+        dice_state[score_action as usize] as u16 * (score_action as u16 + 1)
+    }
+    fn three_of_a_kind_reward(&self, dice_state: &Array1<u8>) -> u16 {
+        // A Yahtzee is always a three of a kind, joker or not
         if is_three_of_a_kind(dice_state) {
-            dice_state.iter().map(|&x| x as u16).sum()
+            sum_of_dice(dice_state)
         } else {
             0
         }
     }
     fn four_of_a_kind_reward(&self, dice_state: &Array1<u8>) -> u16 {
-        if !self.four_of_a_kind() {
-            return 0;
-        }
-        if let Some(yahtzee) = self.yahtzee() {
-            if is_yahtzee(dice_state) {
-                let reward: u16 = dice_state.iter().map(|&x| x as u16).sum();
-                if yahtzee {
-                    // If the dice state is yahtzee and
-                    // the score state has already taken yahtzee
-                    // with a non-zero reward, then we add 100
-                    return reward + 100;
-                } else {
-                    return reward;
-                }
-            }
-        }
         if is_four_of_a_kind(dice_state) {
-            dice_state.iter().map(|&x| x as u16).sum()
+            sum_of_dice(dice_state)
         } else {
             0
         }
     }
-    fn full_house_reward(&self, dice_state: &Array1<u8>) -> u16 {
-        if !self.full_house() {
-            return 0;
-        }
-        let reward: u16 = 25;
-        if let Some(yahtzee) = self.yahtzee() {
-            if is_yahtzee(dice_state) {
-                // Yahtzee is a wild card
-                if yahtzee {
-                    // If the dice state is yahtzee and
-                    // the score state has already taken yahtzee
-                    // with a non-zero reward, then we add 100
-                    return reward + 100;
-                } else {
-                    // If the taken yahtzee reward is zero, then
-                    // we don't gain extra 100 points.
-                    return reward;
-                }
-            }
-        }
-        if is_full_house(dice_state) {
-            reward
+    fn full_house_reward(&self, dice_state: &Array1<u8>, joker: bool) -> u16 {
+        if joker || is_full_house(dice_state) {
+            25
         } else {
             0
         }
     }
-    fn small_straight_reward(&self, dice_state: &Array1<u8>) -> u16 {
-        if !self.small_straight() {
-            return 0;
-        }
-        let reward: u16 = 30;
-        if let Some(yahtzee) = self.yahtzee() {
-            if is_yahtzee(dice_state) {
-                // Yahtzee is a wild card
-                if yahtzee {
-                    // If the dice state is yahtzee and
-                    // the score state has already taken yahtzee
-                    // with a non-zero reward, then we add 100
-                    return reward + 100;
-                } else {
-                    // If the taken yahtzee reward is zero, then
-                    // we don't gain extra 100 points.
-                    return reward;
-                }
-            }
-        }
-        if is_small_straight(dice_state) {
-            reward
+    fn small_straight_reward(&self, dice_state: &Array1<u8>, joker: bool) -> u16 {
+        if joker || is_small_straight(dice_state) {
+            30
         } else {
             0
         }
     }
-    fn large_straight_reward(&self, dice_state: &Array1<u8>) -> u16 {
-        if !self.large_straight() {
-            return 0;
-        }
-        let reward: u16 = 40;
-        if let Some(yahtzee) = self.yahtzee() {
-            if is_yahtzee(dice_state) {
-                // Yahtzee is a wild card
-                if yahtzee {
-                    // If the dice state is yahtzee and
-                    // the score state has already taken yahtzee
-                    // with a non-zero reward, then we add 100
-                    return reward + 100;
-                } else {
-                    // If the taken yahtzee reward is zero, then
-                    // we don't gain extra 100 points.
-                    return reward;
-                }
-            }
-        }
-        if is_large_straight(dice_state) {
-            reward
+    fn large_straight_reward(&self, dice_state: &Array1<u8>, joker: bool) -> u16 {
+        if joker || is_large_straight(dice_state) {
+            40
         } else {
             0
         }
     }
     fn chance_reward(&self, dice_state: &Array1<u8>) -> u16 {
-        if !self.chance() {
-            return 0;
-        }
-        dice_state.iter().map(|&x| x as u16).sum()
+        sum_of_dice(dice_state)
     }
-    fn yahtzee_reward(&self, dice_state: &Array1<u8>) -> u16 {
-        if self.yahtzee().is_none() {
-            return 0;
-        }
+    fn yahtzee_reward(&self, dice_state: &Array1<u8>, rule_set: RuleSet) -> u16 {
         if is_yahtzee(dice_state) {
-            50
+            rule_set.yahtzee_score()
         } else {
             0
         }
     }
-    fn sum_of_upper(&self) -> Option<u8> {
-        let mut sum = 0;
-        if let Some(val) = self.ones() {
-            sum += val;
-        } else {
-            return None;
+    fn n_of_a_kind_reward(&self, dice_state: &Array1<u8>, n: u8) -> u16 {
+        // Yatzy only counts the n dice of the highest face rolled at least n times
+        match dice_state.iter().rposition(|&num_dice| num_dice >= n) {
+            Some(face_index) => n as u16 * (face_index as u16 + 1),
+            None => 0,
         }
-        if let Some(val) = self.twos() {
-            sum += val;
+    }
+    fn n_pairs_reward(&self, dice_state: &Array1<u8>, n: usize) -> u16 {
+        // The n highest different faces rolled at least twice, a full house counts as two pairs
+        let pair_faces = dice_state
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, &num_dice)| num_dice >= 2)
+            .map(|(face_index, _)| face_index as u16 + 1)
+            .take(n)
+            .collect::<Vec<_>>();
+        if pair_faces.len() == n {
+            2 * pair_faces.iter().sum::<u16>()
         } else {
-            return None;
+            0
         }
-        if let Some(val) = self.threes() {
-            sum += val;
+    }
+    fn yatzy_straight_reward(&self, dice_state: &Array1<u8>, shift: usize, length: usize) -> u16 {
+        // 1-2-3-4-5, 2-3-4-5-6 or 1-2-3-4-5-6, scoring the sum of the straight
+        if (shift..shift + length).all(|face_index| dice_state[face_index] >= 1) {
+            (shift..shift + length)
+                .map(|face_index| face_index as u16 + 1)
+                .sum()
         } else {
-            return None;
+            0
         }
-        if let Some(val) = self.fours() {
-            sum += val;
+    }
+    fn yacht_straight_reward(&self, dice_state: &Array1<u8>, shift: usize) -> u16 {
+        // 1-2-3-4-5 or 2-3-4-5-6, both scoring 30
+        if (shift..shift + 5).all(|face_index| dice_state[face_index] >= 1) {
+            30
         } else {
-            return None;
+            0
         }
-        if let Some(val) = self.fives() {
-            sum += val;
-        } else {
-            return None;
+    }
+    fn generala_hand_reward(&self, made: bool, points: u16, served: bool) -> u16 {
+        match (made, served) {
+            (true, true) => points + SERVED_BONUS,
+            (true, false) => points,
+            (false, _) => 0,
         }
-        if let Some(val) = self.sixes() {
-            sum += val;
-        } else {
-            return None;
+    }
+    fn house_reward(&self, dice_state: &Array1<u8>, n: u8, m: u8) -> u16 {
+        // n dice of one face and m of another, scoring the best sum of those dice: a full house
+        // is 3 and 2, a castle 3 and 3 and a tower 4 and 2
+        let mut max_reward = 0;
+        for (n_face_index, &n_num_dice) in dice_state.iter().enumerate() {
+            for (m_face_index, &m_num_dice) in dice_state.iter().enumerate() {
+                if n_face_index != m_face_index && n_num_dice >= n && m_num_dice >= m {
+                    let reward =
+                        n as u16 * (n_face_index as u16 + 1) + m as u16 * (m_face_index as u16 + 1);
+                    max_reward = max_reward.max(reward);
+                }
+            }
         }
-        Some(sum)
+        max_reward
     }
 }
 
 impl Display for ScoreState {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut data = "ScoreState(".to_string();
-        if let Some(val) = self.ones() {
-            data += &format!("ones: {}, ", val);
-        } else {
-            data += "ones: None, ";
-        }
-        if let Some(val) = self.twos() {
-            data += &format!("twos: {}, ", val);
-        } else {
-            data += "twos: None, ";
-        }
-        if let Some(val) = self.threes() {
-            data += &format!("threes: {}, ", val);
-        } else {
-            data += "threes: None, ";
-        }
-        if let Some(val) = self.fours() {
-            data += &format!("fours: {}, ", val);
-        } else {
-            data += "fours: None, ";
-        }
-        if let Some(val) = self.fives() {
-            data += &format!("fives: {}, ", val);
-        } else {
-            data += "fives: None, ";
-        }
-        if let Some(val) = self.sixes() {
-            data += &format!("sixes: {}, ", val);
-        } else {
-            data += "sixes: None, ";
-        }
+        data += &format!("upper_score: {}, ", self.upper_score());
+        data += &format!("ones: {}, ", self.ones());
+        data += &format!("twos: {}, ", self.twos());
+        data += &format!("threes: {}, ", self.threes());
+        data += &format!("fours: {}, ", self.fours());
+        data += &format!("fives: {}, ", self.fives());
+        data += &format!("sixes: {}, ", self.sixes());
         data += &format!("three_of_a_kind: {}, ", self.three_of_a_kind());
         data += &format!("four_of_a_kind: {}, ", self.four_of_a_kind());
         data += &format!("full_house: {}, ", self.full_house());
         data += &format!("small_straight: {}, ", self.small_straight());
         data += &format!("large_straight: {}, ", self.large_straight());
         data += &format!("chance: {}, ", self.chance());
+        data += &format!("one_pair: {}, ", self.one_pair());
+        data += &format!("two_pairs: {}, ", self.two_pairs());
+        data += &format!("three_pairs: {}, ", self.three_pairs());
+        data += &format!("full_straight: {}, ", self.full_straight());
+        data += &format!("castle: {}, ", self.castle());
+        data += &format!("tower: {}, ", self.tower());
+        data += &format!("banked_rerolls: {}, ", self.banked_rerolls());
         if let Some(val) = self.yahtzee() {
             data += &format!("yahtzee: {}", val);
         } else {
@@ -542,7 +923,16 @@ impl From<ScoreState> for u32 {
     }
 }
 
-fn is_three_of_a_kind(dice_state: &Array1<u8>) -> bool {
+pub fn sum_of_dice(dice_state: &Array1<u8>) -> u16 {
+    // The dice state holds the count of each face, so weight each count by its face value
+    dice_state
+        .iter()
+        .enumerate()
+        .map(|(face_index, &num_dice)| num_dice as u16 * (face_index as u16 + 1))
+        .sum()
+}
+
+pub fn is_three_of_a_kind(dice_state: &Array1<u8>) -> bool {
     for num_dice in dice_state.iter() {
         if *num_dice >= 3 {
             return true;
@@ -551,7 +941,7 @@ fn is_three_of_a_kind(dice_state: &Array1<u8>) -> bool {
     false
 }
 
-fn is_four_of_a_kind(dice_state: &Array1<u8>) -> bool {
+pub fn is_four_of_a_kind(dice_state: &Array1<u8>) -> bool {
     for num_dice in dice_state.iter() {
         if *num_dice >= 4 {
             return true;
@@ -560,7 +950,7 @@ fn is_four_of_a_kind(dice_state: &Array1<u8>) -> bool {
     false
 }
 
-fn is_full_house(dice_state: &Array1<u8>) -> bool {
+pub fn is_full_house(dice_state: &Array1<u8>) -> bool {
     let mut found_two = false;
     let mut found_three = false;
     for num_dice in dice_state.iter() {
@@ -573,8 +963,8 @@ fn is_full_house(dice_state: &Array1<u8>) -> bool {
     found_two && found_three
 }
 
-fn is_small_straight(dice_state: &Array1<u8>) -> bool {
-    for shift in 0..=NUM_DICES - 4 {
+pub fn is_small_straight(dice_state: &Array1<u8>) -> bool {
+    for shift in 0..=dice_state.len() - 4 {
         let mut found = true;
         for i in 0..4 {
             if dice_state[shift + i] == 0 {
@@ -589,10 +979,311 @@ fn is_small_straight(dice_state: &Array1<u8>) -> bool {
     false
 }
 
-fn is_large_straight(dice_state: &Array1<u8>) -> bool {
-    dice_state.iter().all(|&x| x == 1)
+pub fn is_large_straight(dice_state: &Array1<u8>) -> bool {
+    for shift in 0..=dice_state.len() - 5 {
+        if (0..5).all(|i| dice_state[shift + i] == 1) {
+            return true;
+        }
+    }
+    false
+}
+
+pub fn is_yahtzee(dice_state: &Array1<u8>) -> bool {
+    // Every dice on the same face, whatever the number of dice
+    let num_dice = dice_state.sum();
+    dice_state.iter().any(|&x| x == num_dice)
 }
 
-fn is_yahtzee(dice_state: &Array1<u8>) -> bool {
-    dice_state.iter().all(|&x| x == NUM_DICES as u8)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reward_evaluation;
+
+    // Taken flags of Ones to Chance
+    const TAKEN_MASK: u32 = 0b1111_1111_1111;
+    use ndarray::array;
+    use std::collections::{HashMap, HashSet};
+
+    fn representative_dice_states(score_action: ScoreAction, num_dice: usize) -> Vec<Array1<u8>> {
+        // One dice state per distinct outcome of apply_action for the given action
+        let num_dice = num_dice as u8;
+        let mut yahtzee = array![0, 0, 0, 0, 0, 0];
+        match score_action {
+            ScoreAction::Ones
+            | ScoreAction::Twos
+            | ScoreAction::Threes
+            | ScoreAction::Fours
+            | ScoreAction::Fives
+            | ScoreAction::Sixes => {
+                // Every number of matching dice without a Yahtzee, then Yahtzees of the matching
+                // and of another face
+                let mut dice_states = (0..num_dice)
+                    .map(|matching_dice| {
+                        let mut dice_state = array![0, 0, 0, 0, 0, 0];
+                        dice_state[score_action as usize] = matching_dice;
+                        dice_state[(score_action as usize + 1) % 6] = num_dice - matching_dice - 1;
+                        dice_state[(score_action as usize + 2) % 6] += 1;
+                        dice_state
+                    })
+                    .collect::<Vec<_>>();
+                yahtzee[score_action as usize] = num_dice;
+                dice_states.push(yahtzee);
+                let mut dice_state = array![0, 0, 0, 0, 0, 0];
+                dice_state[(score_action as usize + 1) % 6] = num_dice;
+                dice_states.push(dice_state);
+                dice_states
+            }
+            _ => {
+                let mut no_yahtzee = array![1, 1, 1, 1, 1, 0];
+                no_yahtzee[5] = num_dice - 5;
+                yahtzee[4] = num_dice;
+                vec![no_yahtzee, yahtzee]
+            }
+        }
+    }
+
+    fn max_rerolls_left(score_state: &ScoreState, rule_set: RuleSet) -> usize {
+        // The most rerolls a turn can leave to bank
+        if rule_set.has_banked_rerolls() {
+            reward_evaluation::turn_start_rerolls(*score_state, rule_set)
+        } else {
+            0
+        }
+    }
+
+    #[test]
+    fn test_encoding_round_trip() {
+        for upper_score in 0..=MAX_UPPER_SCORE {
+            for yahtzee in 0..=YAHTZEE_NOT_TAKEN {
+                for taken in 0..=TAKEN_MASK {
+                    let flag = |score_action: ScoreAction| taken & (0b1 << score_action as u8) != 0;
+                    let score_state = ScoreState::new(
+                        upper_score,
+                        (yahtzee != YAHTZEE_NOT_TAKEN).then_some(yahtzee),
+                        flag(ScoreAction::Ones),
+                        flag(ScoreAction::Twos),
+                        flag(ScoreAction::Threes),
+                        flag(ScoreAction::Fours),
+                        flag(ScoreAction::Fives),
+                        flag(ScoreAction::Sixes),
+                        flag(ScoreAction::ThreeOfAKind),
+                        flag(ScoreAction::FourOfAKind),
+                        flag(ScoreAction::FullHouse),
+                        flag(ScoreAction::SmallStraight),
+                        flag(ScoreAction::LargeStraight),
+                        flag(ScoreAction::Chance),
+                    );
+                    let encoded: u32 = score_state.into();
+                    assert_eq!(ScoreState::from(encoded), score_state);
+                    assert_eq!(score_state.upper_score(), upper_score);
+                    assert_eq!(score_state.yahtzee_field(), yahtzee);
+                    for score_action in ScoreAction::all() {
+                        if score_action == ScoreAction::Yahtzee {
+                            assert_eq!(
+                                score_state.is_taken(score_action),
+                                yahtzee != YAHTZEE_NOT_TAKEN
+                            );
+                        } else {
+                            assert_eq!(score_state.is_taken(score_action), flag(score_action));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_parent_child_relations(rule_set: RuleSet, max_num_taken: usize) {
+        // Walk every state reachable from the empty scorecard with up to max_num_taken boxes
+        // taken and check that apply_action and get_parent_states are inverse of each other.
+        let terminal_states = ScoreState::get_all_terminal_states(rule_set)
+            .into_iter()
+            .collect::<HashSet<_>>();
+        let all_representative_dice_states = ScoreAction::all()
+            .map(|score_action| representative_dice_states(score_action, rule_set.num_dice()));
+        // Each reachable state along with the reachable states it was applied from
+        let mut layer = HashMap::from([(ScoreState::empty(), Vec::new())]);
+        let mut num_reachable_states = 0;
+        for _ in 0..=max_num_taken {
+            num_reachable_states += layer.len();
+            let mut next_layer: HashMap<ScoreState, Vec<ScoreState>> = HashMap::new();
+            for (score_state, applied_from_states) in layer.iter() {
+                let encoded: u32 = (*score_state).into();
+                assert_eq!(ScoreState::from(encoded), *score_state);
+                assert!(score_state.is_valid(rule_set));
+                if score_state.num_taken() == rule_set.score_actions().len() {
+                    assert!(terminal_states.contains(score_state));
+                }
+
+                for score_action in score_state.possible_score_actions(rule_set) {
+                    for dice_state in all_representative_dice_states[score_action as usize].iter() {
+                        let child_state = score_state
+                            .apply_action(score_action, dice_state, rule_set)
+                            .expect("possible_score_actions should only return valid actions");
+                        assert_eq!(child_state.num_taken(), score_state.num_taken() + 1);
+                        for num_rerolls in 0..=max_rerolls_left(score_state, rule_set) {
+                            next_layer
+                                .entry(rule_set.bank_rerolls(child_state, num_rerolls))
+                                .or_default()
+                                .push(*score_state);
+                        }
+                    }
+                }
+
+                let parent_states = score_state.get_parent_states(rule_set);
+                for applied_from_state in applied_from_states.iter() {
+                    assert!(parent_states.contains(applied_from_state));
+                }
+                for parent_state in parent_states {
+                    assert!(parent_state.is_valid(rule_set));
+                    let score_action = ScoreAction::all()
+                        .into_iter()
+                        .find(|&x| score_state.is_taken(x) && !parent_state.is_taken(x))
+                        .expect("parent_state should have exactly one less box taken");
+                    assert!(all_representative_dice_states[score_action as usize]
+                        .iter()
+                        .any(|dice_state| {
+                            let child_state = parent_state
+                                .apply_action(score_action, dice_state, rule_set)
+                                .expect("parent_state should not have score_action taken");
+                            (0..=max_rerolls_left(&parent_state, rule_set)).any(|num_rerolls| {
+                                rule_set.bank_rerolls(child_state, num_rerolls) == *score_state
+                            })
+                        }));
+                }
+            }
+            layer = next_layer;
+        }
+        assert!(num_reachable_states > 1);
+        assert_eq!(
+            layer.is_empty(),
+            max_num_taken == rule_set.score_actions().len()
+        );
+    }
+
+    #[test]
+    fn test_parent_child_relations() {
+        let rule_set = RuleSet::default();
+        check_parent_child_relations(rule_set, rule_set.score_actions().len());
+    }
+
+    #[test]
+    fn test_yatzy_parent_child_relations() {
+        let rule_set = RuleSet::new(Game::Yatzy);
+        check_parent_child_relations(rule_set, rule_set.score_actions().len());
+    }
+
+    #[test]
+    fn test_generala_parent_child_relations() {
+        let rule_set = RuleSet::new(Game::Generala);
+        check_parent_child_relations(rule_set, rule_set.score_actions().len());
+    }
+
+    #[test]
+    fn test_forced_order_parent_child_relations() {
+        let rule_set = RuleSet {
+            forced_order: true,
+            ..RuleSet::default()
+        };
+        check_parent_child_relations(rule_set, rule_set.score_actions().len());
+    }
+
+    #[test]
+    fn test_maxi_yatzy_parent_child_relations() {
+        // Six dice and banked rerolls, the 19 boxes are too many to walk them all
+        check_parent_child_relations(RuleSet::new(Game::MaxiYatzy), 3);
+    }
+
+    #[test]
+    fn test_served_bonus() {
+        // A full house made on the first roll of the turn scores 30 + 5 in Generala, whatever the
+        // number of rolls
+        let full_house = array![0, 0, 3, 2, 0, 0];
+        for num_rolls in [1, 3, 4] {
+            let rule_set = RuleSet {
+                num_rolls,
+                ..RuleSet::new(Game::Generala)
+            };
+            for num_rerolls in 0..num_rolls {
+                let served = num_rerolls == num_rolls - 1;
+                assert_eq!(rule_set.is_served(num_rerolls), served);
+                assert_eq!(
+                    ScoreState::empty().reward_on_roll(
+                        ScoreAction::FullHouse,
+                        &full_house,
+                        num_rerolls,
+                        rule_set
+                    ),
+                    if served { 35 } else { 30 }
+                );
+            }
+        }
+        // Other games have no served bonus
+        assert!(!RuleSet::default().is_served(2));
+        assert_eq!(
+            ScoreState::empty().reward_on_roll(
+                ScoreAction::FullHouse,
+                &full_house,
+                2,
+                RuleSet::default()
+            ),
+            25
+        );
+    }
+
+    fn check_reachable_states(rule_set: RuleSet, max_num_taken: usize) -> Vec<Vec<ScoreState>> {
+        // Every reachable state is valid, clamped at the bonus threshold and applied from a
+        // reachable state of the previous layer
+        let reachable_states = ScoreState::get_reachable_states(rule_set, max_num_taken);
+        assert_eq!(reachable_states.len(), max_num_taken + 1);
+        let mut previous_layer = HashSet::new();
+        for (num_taken, layer) in reachable_states.iter().enumerate() {
+            for score_state in layer.iter() {
+                assert_eq!(score_state.num_taken(), num_taken);
+                assert!(score_state.is_valid(rule_set));
+                assert!(score_state.upper_score() <= rule_set.upper_score_threshold());
+                if num_taken > 0 {
+                    assert!(score_state
+                        .get_parent_states(rule_set)
+                        .iter()
+                        .any(|parent_state| previous_layer.contains(parent_state)));
+                }
+            }
+            previous_layer = layer.iter().copied().collect();
+        }
+        reachable_states
+    }
+
+    #[test]
+    fn test_reachable_states() {
+        let rule_set = RuleSet::default();
+        let reachable_states = check_reachable_states(rule_set, rule_set.score_actions().len());
+        let terminal_layer = &reachable_states[rule_set.score_actions().len()];
+        let terminal_states = ScoreState::get_all_terminal_states(rule_set);
+        assert!(terminal_layer.len() < terminal_states.len());
+        assert!(terminal_layer
+            .iter()
+            .all(|terminal_state| terminal_states.contains(terminal_state)));
+    }
+
+    #[test]
+    fn test_maxi_yatzy_reachable_states() {
+        let rule_set = RuleSet::new(Game::MaxiYatzy);
+        let reachable_states = check_reachable_states(rule_set, 4);
+        // Six sixes in the Sixes box
+        assert!(reachable_states[1]
+            .iter()
+            .any(|score_state| score_state.upper_score() == 36));
+        // Every turn can bank its own rerolls and the ones banked before, up to the limit
+        for (num_taken, layer) in reachable_states.iter().enumerate() {
+            let max_banked_rerolls = layer
+                .iter()
+                .map(|score_state| score_state.banked_rerolls())
+                .max()
+                .unwrap();
+            assert_eq!(
+                max_banked_rerolls,
+                (2 * num_taken as u8).min(MAX_BANKED_REROLLS)
+            );
+        }
+    }
 }
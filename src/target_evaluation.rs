@@ -0,0 +1,108 @@
+use crate::dice_states::TransitionFunction;
+use crate::errors::Error;
+use crate::objectives::Objective;
+use crate::reward_evaluation::{self, IntKeyedArrayMap};
+use crate::rule_sets::RuleSet;
+use crate::score_states::ScoreState;
+use ndarray::prelude::*;
+
+// The "beat a target" objective extends the ScoreState with the points banked so far. Since only
+// the points still needed to reach the target matter, each ScoreState stores the probability of
+// collecting at least `need` more points, for need in 0..=target, at the start of its turn.
+
+pub fn terminal_target_probability(target: u16, rule_set: RuleSet) -> IntKeyedArrayMap {
+    let num_needs = target as usize + 1;
+    let terminal_states = ScoreState::get_all_terminal_states(rule_set);
+    let mut terminal_probability = IntKeyedArrayMap::new(terminal_states.iter(), (1, num_needs));
+    for terminal_state in terminal_states.iter() {
+        // The terminal reward is certain, so any need up to it is met and any need above is not
+        let terminal_reward = terminal_state.terminal_reward(rule_set) as usize;
+        let probability = Array2::from_shape_fn((1, num_needs), |(_, need)| {
+            if need <= terminal_reward {
+                1.0
+            } else {
+                0.0
+            }
+        });
+        terminal_probability.set((*terminal_state).into(), &probability);
+    }
+    terminal_probability
+}
+
+pub fn calculate_and_save_all_target_probability(
+    all_dice_states: &[Array1<u8>],
+    transition_function: &TransitionFunction,
+    rule_set: RuleSet,
+    target: u16,
+    hdf5_file: &hdf5::File,
+) -> Result<(), Error> {
+    // The probability of collecting every need at the start of the turn of every ScoreState
+    let objective = Objective::BeatTarget(target);
+    reward_evaluation::calculate_and_save_all_turn_start_value(
+        transition_function,
+        rule_set,
+        &objective,
+        &objective.dataset_name(),
+        terminal_target_probability(target, rule_set),
+        hdf5_file,
+        |score_state, _, previous_layer_probability| {
+            calculate_score_state_target_probability(
+                score_state,
+                previous_layer_probability,
+                all_dice_states,
+                transition_function,
+                rule_set,
+                target,
+            )
+        },
+    )
+}
+
+pub fn calculate_score_state_target_probability(
+    score_state: ScoreState,
+    previous_layer_probability: &IntKeyedArrayMap,
+    all_dice_states: &[Array1<u8>],
+    transition_function: &TransitionFunction,
+    rule_set: RuleSet,
+    target: u16,
+) -> Array3<f32> {
+    // Calculate the probability of collecting at least `need` more points from a ScoreState,
+    // and return it as a ndarray of shape (num_rolls, num_dice_states, target + 1).
+    let num_dice_states: usize = all_dice_states.len();
+    let num_needs = target as usize + 1;
+    let mut score_state_probability =
+        Array3::<f32>::zeros((rule_set.num_rolls, num_dice_states, num_needs));
+
+    // 0 reroll, the best ScoreAction can differ for every need, so we maximize each need
+    // separately over
+    // 1 if Reward(ScoreState, DiceState, ScoreAction) >= need
+    // else Probability(ChildScoreState, need - Reward) at the start of its turn
+    for (dice_state_index, dice_state) in all_dice_states.iter().enumerate() {
+        let mut max_probability = score_state_probability.slice_mut(s![0, dice_state_index, ..]);
+        for score_action in score_state.legal_score_actions(dice_state, rule_set) {
+            let action_reward = score_state.reward(score_action, dice_state, rule_set) as usize;
+            let child_score_state = score_state
+                .apply_action(score_action, dice_state, rule_set)
+                .expect("legal_score_actions should only return valid actions");
+            let child_probability = previous_layer_probability
+                .get(child_score_state.into())
+                .expect("previous_layer_probability should contain all reachable ScoreStates");
+            for need in 0..num_needs {
+                let probability = if need <= action_reward {
+                    1.0
+                } else {
+                    child_probability[[0, need - action_reward]]
+                };
+                max_probability[need] = max_probability[need].max(probability);
+            }
+        }
+    }
+
+    // 1 and 2 reroll, same as the expected reward but every need is maximized separately over
+    // the possible KeepAction
+    reward_evaluation::maximize_over_keep_actions(
+        &mut score_state_probability,
+        transition_function,
+    );
+    score_state_probability
+}
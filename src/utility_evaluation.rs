@@ -0,0 +1,97 @@
+use crate::dice_states::TransitionFunction;
+use crate::distribution_evaluation::MAX_SCORE;
+use crate::errors::Error;
+use crate::objectives::{Objective, Utility};
+use crate::reward_evaluation::{self, IntKeyedArrayMap};
+use crate::rule_sets::RuleSet;
+use crate::score_states::ScoreState;
+use ndarray::prelude::*;
+
+// Unless the utility is linear, the best action depends on the points banked so far and not only
+// on the ScoreState. Each ScoreState stores the expected utility of the final score for every
+// banked reward in 0..=MAX_SCORE at the start of its turn, the same way the target probability
+// stores every need.
+
+pub fn terminal_utility(utility: &Utility, rule_set: RuleSet) -> IntKeyedArrayMap {
+    let terminal_states = ScoreState::get_all_terminal_states(rule_set);
+    let mut terminal_utility = IntKeyedArrayMap::new(terminal_states.iter(), (1, MAX_SCORE + 1));
+    for terminal_state in terminal_states.iter() {
+        // The final score is the banked reward plus the terminal reward
+        let terminal_reward = terminal_state.terminal_reward(rule_set) as usize;
+        let expected_utility = Array2::from_shape_fn((1, MAX_SCORE + 1), |(_, banked_reward)| {
+            utility.utility(banked_reward + terminal_reward)
+        });
+        terminal_utility.set((*terminal_state).into(), &expected_utility);
+    }
+    terminal_utility
+}
+
+pub fn calculate_and_save_all_utility(
+    all_dice_states: &[Array1<u8>],
+    transition_function: &TransitionFunction,
+    rule_set: RuleSet,
+    utility: &Utility,
+    hdf5_file: &hdf5::File,
+) -> Result<(), Error> {
+    // The expected utility for every banked reward at the start of the turn of every ScoreState
+    let objective = Objective::MaximizeUtility(utility.clone());
+    reward_evaluation::calculate_and_save_all_turn_start_value(
+        transition_function,
+        rule_set,
+        &objective,
+        &objective.dataset_name(),
+        terminal_utility(utility, rule_set),
+        hdf5_file,
+        |score_state, _, previous_layer_utility| {
+            calculate_score_state_utility(
+                score_state,
+                previous_layer_utility,
+                all_dice_states,
+                transition_function,
+                rule_set,
+            )
+        },
+    )
+}
+
+pub fn calculate_score_state_utility(
+    score_state: ScoreState,
+    previous_layer_utility: &IntKeyedArrayMap,
+    all_dice_states: &[Array1<u8>],
+    transition_function: &TransitionFunction,
+    rule_set: RuleSet,
+) -> Array3<f32> {
+    // Calculate the expected utility of a ScoreState for every banked reward, and return it as a
+    // ndarray of shape (num_rolls, num_dice_states, MAX_SCORE + 1).
+    let num_dice_states: usize = all_dice_states.len();
+    let mut score_state_utility = Array3::<f32>::from_elem(
+        (rule_set.num_rolls, num_dice_states, MAX_SCORE + 1),
+        f32::MIN,
+    );
+
+    // 0 reroll, the expected utility is maximized for every banked reward separately over
+    // Utility(ChildScoreState, BankedReward + Reward(ScoreState, DiceState, ScoreAction)) at the
+    // start of its turn
+    for (dice_state_index, dice_state) in all_dice_states.iter().enumerate() {
+        let mut max_utility = score_state_utility.slice_mut(s![0, dice_state_index, ..]);
+        for score_action in score_state.legal_score_actions(dice_state, rule_set) {
+            let action_reward = score_state.reward(score_action, dice_state, rule_set) as usize;
+            let child_score_state = score_state
+                .apply_action(score_action, dice_state, rule_set)
+                .expect("legal_score_actions should only return valid actions");
+            let child_utility = previous_layer_utility
+                .get(child_score_state.into())
+                .expect("previous_layer_utility should contain all reachable ScoreStates");
+            for (banked_reward, max_utility) in max_utility.iter_mut().enumerate() {
+                // Banked rewards too high to be reached are clamped to stay in the table
+                let child_banked_reward = (banked_reward + action_reward).min(MAX_SCORE);
+                *max_utility = max_utility.max(child_utility[[0, child_banked_reward]]);
+            }
+        }
+    }
+
+    // 1 and 2 reroll, same as the expected reward but every banked reward is maximized
+    // separately over the possible KeepAction
+    reward_evaluation::maximize_over_keep_actions(&mut score_state_utility, transition_function);
+    score_state_utility
+}